@@ -1,84 +1,221 @@
-use std::collections::HashSet;
+use std::ops::RangeInclusive;
 
-use crate::util::DIRS;
+use crate::util::{coords, Heading, HEADINGS};
 use aoc_runner_derive::{aoc, aoc_generator};
 
+// Heights are usually one digit each, but lines with whitespace are read as
+// whitespace-separated numbers instead. Either way, `.` is impassable.
 #[aoc_generator(day10)]
-pub fn gen(input: &str) -> Vec<Vec<u8>> {
+pub fn gen(input: &str) -> Vec<Vec<Option<u32>>> {
     input
         .lines()
         .map(|l| {
-            l.bytes()
-                .map(|b| if b == b'.' { 10 } else { b - b'0' })
-                .collect()
+            if l.contains(char::is_whitespace) {
+                l.split_whitespace().map(|h| h.parse().ok()).collect()
+            } else {
+                l.chars().map(|ch| ch.to_digit(10)).collect()
+            }
         })
         .collect()
 }
 
-fn score(curr: (usize, usize), grid: &[Vec<u8>]) -> HashSet<(usize, usize)> {
-    match grid[curr.0][curr.1] {
-        10.. => HashSet::new(),
-        9 => HashSet::from([curr]),
-        me => {
-            // For everyone around me, see if anyone is me plus one
-            let mut out = HashSet::new();
-            let bounds = (grid.len(), grid[0].len());
-            for d in DIRS {
-                if let Some((r, c)) = d.step_bounded(curr, bounds) {
-                    if grid[r][c] == me + 1 {
-                        // Follow this
-                        out.extend(&score((r, c), grid));
-                    }
-                }
-            }
-            out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Neighborhood {
+    #[default]
+    Four,
+    Eight,
+}
+
+impl Neighborhood {
+    fn headings(&self) -> impl Iterator<Item = Heading> + '_ {
+        HEADINGS
+            .into_iter()
+            .filter(move |h| *self == Neighborhood::Eight || h.is_cardinal())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TrailRules {
+    // How much the height may change with each step. Either every step goes
+    // up, or every step goes down
+    pub delta: RangeInclusive<i64>,
+    pub neighbors: Neighborhood,
+    pub start: u32,
+    pub end: u32,
+}
+
+impl Default for TrailRules {
+    fn default() -> Self {
+        Self {
+            delta: 1..=1,
+            neighbors: Neighborhood::Four,
+            start: 0,
+            end: 9,
         }
     }
 }
 
-fn rate(curr: (usize, usize), grid: &[Vec<u8>]) -> usize {
-    match grid[curr.0][curr.1] {
-        10.. => 0,
-        9 => 1,
-        me => {
-            // For everyone around me, see if anyone is me plus one
-            let mut count = 0;
-            let bounds = (grid.len(), grid[0].len());
-            for d in DIRS {
-                if let Some((r, c)) = d.step_bounded(curr, bounds) {
-                    if grid[r][c] == me + 1 {
-                        // Follow this
-                        count += rate((r, c), grid);
-                    }
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum BadRules {
+    // No height change would ever be allowed
+    EmptyDelta(RangeInclusive<i64>),
+    // Flat steps (and so going both up and down) aren't allowed, since a
+    // trail could then wander back and forth forever
+    FlatSteps(RangeInclusive<i64>),
+}
+
+impl TrailRules {
+    pub fn ascending(&self) -> Result<bool, BadRules> {
+        if self.delta.is_empty() {
+            Err(BadRules::EmptyDelta(self.delta.clone()))
+        } else if self.delta.contains(&0) {
+            Err(BadRules::FlatSteps(self.delta.clone()))
+        } else {
+            Ok(*self.delta.start() > 0)
+        }
+    }
+    fn can_step(&self, from: u32, to: u32) -> bool {
+        self.delta.contains(&(i64::from(to) - i64::from(from)))
+    }
+}
+
+type Posn = (usize, usize);
+
+// Which peaks each cell can reach, and in how many distinct ways, worked out
+// once for the whole map by going back from the peaks one level at a time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trails {
+    heights: Vec<Vec<Option<u32>>>,
+    rules: TrailRules,
+    peaks: Vec<Posn>,
+    // Bitset over `peaks`, for every cell
+    reach: Vec<Vec<u64>>,
+    paths: Vec<usize>,
+}
+
+impl Trails {
+    pub fn new(map: &[Vec<Option<u32>>], rules: &TrailRules) -> Result<Self, BadRules> {
+        let ascending = rules.ascending()?;
+        let (rows, cols) = (map.len(), map.first().map_or(0, Vec::len));
+        let peaks = coords((rows, cols))
+            .filter(|&(r, c)| map[r][c] == Some(rules.end))
+            .collect::<Vec<_>>();
+        let words = peaks.len().div_ceil(64);
+        let mut reach = vec![vec![0u64; words]; rows * cols];
+        let mut paths = vec![0; rows * cols];
+        for (p, &(r, c)) in peaks.iter().enumerate() {
+            reach[r * cols + c][p / 64] |= 1 << (p % 64);
+            paths[r * cols + c] = 1;
+        }
+        // Every step goes the same way, so handling the cells closest to the
+        // end height first means wherever they step to is already done.
+        // Trails stop at the first peak they find.
+        let mut order = coords((rows, cols))
+            .filter_map(|(r, c)| map[r][c].map(|h| (h, (r, c))))
+            .filter(|&(h, _)| h != rules.end)
+            .collect::<Vec<_>>();
+        order.sort();
+        if ascending {
+            order.reverse();
+        }
+        for (height, (r, c)) in order {
+            for h in rules.neighbors.headings() {
+                let Some((nr, nc)) = h.step_bounded((r, c), (rows, cols)) else {
+                    continue;
+                };
+                if !map[nr][nc].is_some_and(|next| rules.can_step(height, next)) {
+                    continue;
+                }
+                let (me, them) = (r * cols + c, nr * cols + nc);
+                paths[me] += paths[them];
+                let theirs = std::mem::take(&mut reach[them]);
+                for (mine, theirs) in reach[me].iter_mut().zip(&theirs) {
+                    *mine |= theirs;
                 }
+                reach[them] = theirs;
+            }
+        }
+        Ok(Self {
+            heights: map.to_vec(),
+            rules: rules.clone(),
+            peaks,
+            reach,
+            paths,
+        })
+    }
+    fn idx(&self, posn: Posn) -> usize {
+        posn.0 * self.heights[0].len() + posn.1
+    }
+    fn bounds(&self) -> (usize, usize) {
+        (self.heights.len(), self.heights.first().map_or(0, Vec::len))
+    }
+    fn reaches(&self, posn: Posn, peak: usize) -> bool {
+        self.reach[self.idx(posn)][peak / 64] & (1 << (peak % 64)) != 0
+    }
+    pub fn trailheads(&self) -> impl Iterator<Item = Posn> + '_ {
+        coords(self.bounds()).filter(|&(r, c)| self.heights[r][c] == Some(self.rules.start))
+    }
+    // How many different peaks can be reached from here
+    pub fn score(&self, posn: Posn) -> usize {
+        self.reach[self.idx(posn)]
+            .iter()
+            .map(|w| w.count_ones() as usize)
+            .sum()
+    }
+    // How many different trails lead from here to any peak
+    pub fn rating(&self, posn: Posn) -> usize {
+        self.paths[self.idx(posn)]
+    }
+    // Up to `limit` of the trails from `head` to `summit`, as every position
+    // along the way
+    pub fn trails(&self, head: Posn, summit: Posn, limit: usize) -> Vec<Vec<Posn>> {
+        let mut found = vec![];
+        let Some(peak) = self.peaks.iter().position(|&p| p == summit) else {
+            return found;
+        };
+        if !self.reaches(head, peak) {
+            return found;
+        }
+        let mut trail = vec![head];
+        self.follow(&mut trail, peak, limit, &mut found);
+        found
+    }
+    fn follow(&self, trail: &mut Vec<Posn>, peak: usize, limit: usize, found: &mut Vec<Vec<Posn>>) {
+        let curr = *trail.last().unwrap();
+        if curr == self.peaks[peak] {
+            found.push(trail.clone());
+            return;
+        }
+        let Some(height) = self.heights[curr.0][curr.1] else {
+            return;
+        };
+        for h in self.rules.neighbors.headings() {
+            if found.len() >= limit {
+                return;
+            }
+            let Some(next) = h.step_bounded(curr, self.bounds()) else {
+                continue;
+            };
+            // Only bother going places that can still get to the summit
+            let steppable =
+                self.heights[next.0][next.1].is_some_and(|to| self.rules.can_step(height, to));
+            if steppable && self.reaches(next, peak) {
+                trail.push(next);
+                self.follow(trail, peak, limit, found);
+                trail.pop();
             }
-            count
         }
     }
 }
 
 #[aoc(day10, part1)]
-pub fn part1(map: &[Vec<u8>]) -> usize {
-    map.iter()
-        .enumerate()
-        .flat_map(|(r, row)| {
-            row.iter()
-                .enumerate()
-                .filter(|&(_, h)| (*h == 0))
-                .map(move |(c, _)| score((r, c), map).len())
-        })
-        .sum()
+pub fn part1(map: &[Vec<Option<u32>>]) -> usize {
+    let trails = Trails::new(map, &TrailRules::default()).unwrap();
+    trails.trailheads().map(|h| trails.score(h)).sum()
 }
 
 #[aoc(day10, part2)]
-pub fn part2(map: &[Vec<u8>]) -> usize {
-    map.iter()
-        .enumerate()
-        .flat_map(|(r, row)| {
-            row.iter()
-                .enumerate()
-                .filter(|&(_, h)| (*h == 0))
-                .map(move |(c, _)| rate((r, c), map))
-        })
-        .sum()
+pub fn part2(map: &[Vec<Option<u32>>]) -> usize {
+    let trails = Trails::new(map, &TrailRules::default()).unwrap();
+    trails.trailheads().map(|h| trails.rating(h)).sum()
 }
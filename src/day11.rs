@@ -1,57 +1,278 @@
-use core::str;
-use std::{collections::HashMap, ops::Rem};
+use std::{
+    collections::{HashMap, HashSet},
+    num::NonZeroU64,
+};
 
 use aoc_runner_derive::{aoc, aoc_generator};
+use petgraph::prelude::*;
 
-fn blink(stone: &[u8]) -> Vec<Vec<u8>> {
-    if stone == b"0" {
-        vec![b"1".to_vec()]
-    } else if stone.len().rem(2) == 0 {
-        let (left, right) = stone.split_at(stone.len() / 2);
-        let idx = right
+fn digits(stone: u64) -> u32 {
+    stone.checked_ilog10().unwrap_or(0) + 1
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Predicate {
+    Always,
+    Equals(u64),
+    // The number of digits is a multiple of this
+    DigitsMultipleOf(u32),
+}
+
+impl Predicate {
+    pub fn matches(&self, stone: u64) -> bool {
+        match self {
+            Predicate::Always => true,
+            Predicate::Equals(n) => stone == *n,
+            Predicate::DigitsMultipleOf(n) => digits(stone).is_multiple_of(*n),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transform {
+    Replace(u64),
+    Multiply(u64),
+    // Split the digits into this many parts, as evenly as possible; leftover
+    // digits go to the leftmost parts
+    Split(u32),
+}
+
+impl Transform {
+    // None if the stone gets too big to engrave
+    pub fn apply(&self, stone: u64) -> Option<Vec<u64>> {
+        match self {
+            Transform::Replace(n) => Some(vec![*n]),
+            Transform::Multiply(n) => Some(vec![stone.checked_mul(*n)?]),
+            Transform::Split(parts) => {
+                let d = digits(stone);
+                let mut rest = stone;
+                let mut out = vec![];
+                for p in (0..*parts).rev() {
+                    let size = d / parts + u32::from(p < d % parts);
+                    if size == 0 {
+                        continue;
+                    }
+                    let shift = 10u64.pow(size);
+                    out.push(rest % shift);
+                    rest /= shift;
+                }
+                out.reverse();
+                Some(out)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rule {
+    pub when: Predicate,
+    pub then: Transform,
+}
+
+// The first rule that matches a stone is the one that applies; if none do,
+// the stone stays as it is
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RuleSet {
+    pub rules: Vec<Rule>,
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        Self {
+            rules: vec![
+                Rule {
+                    when: Predicate::Equals(0),
+                    then: Transform::Replace(1),
+                },
+                Rule {
+                    when: Predicate::DigitsMultipleOf(2),
+                    then: Transform::Split(2),
+                },
+                Rule {
+                    when: Predicate::Always,
+                    then: Transform::Multiply(2024),
+                },
+            ],
+        }
+    }
+}
+
+type Matrix = Vec<Vec<u128>>;
+
+fn mat_mul(a: &Matrix, b: &Matrix, modulus: Option<NonZeroU64>) -> Option<Matrix> {
+    let n = a.len();
+    let mut out = vec![vec![0u128; n]; n];
+    for i in 0..n {
+        for k in (0..n).filter(|&k| a[i][k] != 0) {
+            for j in 0..n {
+                let prod = a[i][k].checked_mul(b[k][j])?;
+                out[i][j] = match modulus {
+                    Some(m) => (out[i][j] + prod % u128::from(m.get())) % u128::from(m.get()),
+                    None => out[i][j].checked_add(prod)?,
+                };
+            }
+        }
+    }
+    Some(out)
+}
+
+impl RuleSet {
+    pub fn blink(&self, stone: u64) -> Option<Vec<u64>> {
+        match self.rules.iter().find(|r| r.when.matches(stone)) {
+            Some(rule) => rule.then.apply(stone),
+            None => Some(vec![stone]),
+        }
+    }
+    // Every stone value that can ever show up, starting from `stones`. None
+    // if there are more than `limit` of them (or a stone gets too big)
+    pub fn closure(&self, stones: &[u64], limit: usize) -> Option<Vec<u64>> {
+        let mut seen = stones.iter().copied().collect::<HashSet<_>>();
+        let mut queue = seen.iter().copied().collect::<Vec<_>>();
+        while let Some(stone) = queue.pop() {
+            for next in self.blink(stone)? {
+                if seen.insert(next) {
+                    if seen.len() > limit {
+                        return None;
+                    }
+                    queue.push(next);
+                }
+            }
+        }
+        let mut closure = seen.into_iter().collect::<Vec<_>>();
+        closure.sort();
+        Some(closure)
+    }
+    // Each stone value points at what it turns into, weighted by how many of
+    // them it makes
+    pub fn transition_graph(&self, stones: &[u64], limit: usize) -> Option<DiGraph<u64, u64>> {
+        let closure = self.closure(stones, limit)?;
+        let mut graph = DiGraph::new();
+        let nodes = closure
             .iter()
-            .position(|r| *r != b'0')
-            .unwrap_or(right.len() - 1);
-        vec![left.to_vec(), right[idx..].to_vec()]
-    } else {
-        // Extract a number from this
-        let num: u64 = str::from_utf8(stone).unwrap().parse().unwrap();
-        vec![format!("{}", num * 2024).as_bytes().to_vec()]
+            .map(|&s| (s, graph.add_node(s)))
+            .collect::<HashMap<_, _>>();
+        for &stone in &closure {
+            let mut made: HashMap<u64, u64> = HashMap::new();
+            for next in self.blink(stone)? {
+                *made.entry(next).or_default() += 1;
+            }
+            for (next, count) in made {
+                graph.add_edge(nodes[&stone], nodes[&next], count);
+            }
+        }
+        Some(graph)
+    }
+    // Count the stones after `blinks` by raising the transition matrix to
+    // that power, optionally modulo something to keep the numbers in check.
+    // The matrix is as wide as the closure, so this is only quick when the
+    // closure is small.
+    pub fn count_by_matrix(
+        &self,
+        stones: &[u64],
+        blinks: u64,
+        limit: usize,
+        modulus: Option<NonZeroU64>,
+    ) -> Option<u128> {
+        let graph = self.transition_graph(stones, limit)?;
+        let n = graph.node_count();
+        let mut step = vec![vec![0u128; n]; n];
+        for e in graph.edge_references() {
+            step[e.source().index()][e.target().index()] += u128::from(*e.weight());
+        }
+        let mut power = (0..n)
+            .map(|i| (0..n).map(|j| u128::from(i == j)).collect())
+            .collect::<Matrix>();
+        let mut remaining = blinks;
+        while remaining > 0 {
+            if remaining & 1 == 1 {
+                power = mat_mul(&power, &step, modulus)?;
+            }
+            remaining >>= 1;
+            if remaining > 0 {
+                step = mat_mul(&step, &step, modulus)?;
+            }
+        }
+        let index = graph
+            .node_indices()
+            .map(|i| (graph[i], i.index()))
+            .collect::<HashMap<_, _>>();
+        stones.iter().try_fold(0u128, |acc, s| {
+            let row: u128 = power[index[s]]
+                .iter()
+                .try_fold(0u128, |a, &c| match modulus {
+                    Some(m) => Some((a + c) % u128::from(m.get())),
+                    None => a.checked_add(c),
+                })?;
+            match modulus {
+                Some(m) => Some((acc + row) % u128::from(m.get())),
+                None => acc.checked_add(row),
+            }
+        })
+    }
+}
+
+// Counts stones a blink at a time, keeping just how many of each value there
+// are, and remembering what each value turns into. None means there are too
+// many to count.
+#[derive(Debug, Clone, Default)]
+pub struct Stones {
+    rules: RuleSet,
+    memo: HashMap<u64, Option<Vec<u64>>>,
+}
+
+impl Stones {
+    pub fn new(rules: RuleSet) -> Self {
+        Self {
+            rules,
+            memo: HashMap::new(),
+        }
+    }
+    pub fn count(&mut self, stone: u64, blinks: usize) -> Option<u128> {
+        self.count_after(&[stone], blinks)
+    }
+    pub fn count_after(&mut self, stones: &[u64], blinks: usize) -> Option<u128> {
+        let mut counts: HashMap<u64, u128> = HashMap::new();
+        for &s in stones {
+            *counts.entry(s).or_default() += 1;
+        }
+        for _ in 0..blinks {
+            let mut next: HashMap<u64, u128> = HashMap::new();
+            for (stone, n) in counts {
+                let rules = &self.rules;
+                let made = self.memo.entry(stone).or_insert_with(|| rules.blink(stone));
+                // Any count overflowing (or stone getting too big) means the
+                // total can't be counted either, so there's no point going on
+                for &s in made.as_ref()? {
+                    let count = next.entry(s).or_default();
+                    *count = count.checked_add(n)?;
+                }
+            }
+            counts = next;
+        }
+        counts
+            .values()
+            .try_fold(0u128, |acc, &n| acc.checked_add(n))
     }
 }
 
 #[aoc_generator(day11)]
-pub fn gen(input: &str) -> Vec<Vec<u8>> {
+pub fn gen(input: &str) -> Vec<u64> {
     input
         .split_ascii_whitespace()
-        .map(|s| s.as_bytes().to_vec())
+        .map(|s| s.parse().unwrap())
         .collect()
 }
 
 #[aoc(day11, part1)]
-pub fn part1(stones: &[Vec<u8>]) -> usize {
-    let mut stones = stones.to_vec();
-    for _ in 0..25 {
-        stones = stones.iter().flat_map(|s| blink(s)).collect();
-    }
-    stones.len()
+pub fn part1(stones: &[u64]) -> u128 {
+    Stones::default()
+        .count_after(stones, 25)
+        .expect("Too many stones")
 }
 
 #[aoc(day11, part2)]
-pub fn part2(stones: &[Vec<u8>]) -> usize {
-    let mut summary = HashMap::new();
-    for s in stones {
-        *summary.entry(s.clone()).or_default() += 1;
-    }
-    for _ in 0..75 {
-        let mut next_summary = HashMap::new();
-        summary.into_iter().for_each(|(s, c)| {
-            for stone in blink(&s) {
-                *next_summary.entry(stone).or_default() += c;
-            }
-        });
-        summary = next_summary;
-    }
-
-    summary.values().sum()
+pub fn part2(stones: &[u64]) -> u128 {
+    Stones::default()
+        .count_after(stones, 75)
+        .expect("Too many stones")
 }
@@ -1,56 +1,248 @@
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 use aoc_runner_derive::{aoc, aoc_generator};
 
-use crate::util::{Direction, DIRS};
+use crate::util::{coords, Direction, DIRS};
 
 #[aoc_generator(day12)]
-pub fn gen(input: &str) -> HashMap<u8, BTreeSet<(usize, usize)>> {
-    let mut out: HashMap<u8, BTreeSet<(usize, usize)>> = HashMap::new();
+pub fn gen(input: &str) -> Vec<Vec<u8>> {
+    input.lines().map(|l| l.as_bytes().to_vec()).collect()
+}
 
-    let iter = input.lines().enumerate().flat_map(move |(r, line)| {
-        line.as_bytes()
-            .iter()
-            .enumerate()
-            .map(move |(c, b)| (*b, (r, c)))
-    });
+type Posn = (usize, usize);
 
-    for (b, posn) in iter {
-        out.entry(b).or_default().insert(posn);
-    }
-    out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegionReport {
+    pub id: usize,
+    pub plant: u8,
+    pub area: usize,
+    pub perimeter: usize,
+    pub sides: usize,
+    // Gaps that only touch diagonally count as the same hole
+    pub holes: usize,
+    // Top-left and bottom-right plots, inclusive
+    pub bounding_box: (Posn, Posn),
+    // The only region on the other side of the outer fence, if there is one
+    // and the region doesn't touch the edge of the garden
+    pub enclosed_by: Option<usize>,
+    // Each fence as the (row, col) lattice points at its corners, with the
+    // region always on the right (so clockwise outside, counterclockwise
+    // around holes). The outer fence comes first, then one per hole
+    pub outline: Vec<Vec<Posn>>,
+    pub price: usize,
+    pub bulk_price: usize,
 }
 
-fn explore(curr: (usize, usize), posns: &mut BTreeSet<(usize, usize)>) -> BTreeSet<(usize, usize)> {
-    let mut out = BTreeSet::new();
-    out.insert(curr);
-    for d in DIRS {
-        if let Some(new) = d.step(curr) {
-            if posns.remove(&new) {
-                out.extend(explore(new, posns));
-            }
-        }
-    }
-    out
+// Every plot labelled with the region it belongs to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Regions {
+    bounds: (usize, usize),
+    labels: Vec<usize>,
+    plants: Vec<u8>,
+    members: Vec<Vec<Posn>>,
+    areas: Vec<usize>,
+    perimeters: Vec<usize>,
+    sides: Vec<usize>,
 }
 
-fn compute(region: &BTreeSet<(usize, usize)>) -> usize {
-    let area = region.len();
-    let mut perim = area * 4;
-    for p1 in region {
-        for p2 in region {
-            if p1 == p2 {
+impl Regions {
+    pub fn new(garden: &[Vec<u8>]) -> Self {
+        let bounds = (garden.len(), garden.first().map_or(0, Vec::len));
+        let idx = |(r, c): Posn| r * bounds.1 + c;
+        let mut labels = vec![usize::MAX; bounds.0 * bounds.1];
+        let mut plants = vec![];
+        let mut members = vec![];
+        for start in coords(bounds) {
+            if labels[idx(start)] != usize::MAX {
                 continue;
             }
-            let (r1, c1) = p1;
-            let (r2, c2) = p2;
-            if (r1 == r2 && c1.abs_diff(*c2) == 1) || (c1 == c2 && r1.abs_diff(*r2) == 1) {
-                perim -= 1;
+            let id = plants.len();
+            let plant = garden[start.0][start.1];
+            let mut region = vec![];
+            let mut stack = vec![start];
+            labels[idx(start)] = id;
+            while let Some(posn) = stack.pop() {
+                region.push(posn);
+                for d in DIRS {
+                    let Some(next) = d.step_bounded(posn, bounds) else {
+                        continue;
+                    };
+                    if labels[idx(next)] == usize::MAX && garden[next.0][next.1] == plant {
+                        labels[idx(next)] = id;
+                        stack.push(next);
+                    }
+                }
             }
+            plants.push(plant);
+            members.push(region);
+        }
+        // Every side of a plot that doesn't face the same region needs
+        // fencing. A region has as many sides as corners, and each corner
+        // belongs to exactly one plot: either it's fenced on two neighbouring
+        // sides (convex), or it isn't but the plot between them is someone
+        // else's (concave).
+        let mut areas = vec![0; plants.len()];
+        let mut perimeters = vec![0; plants.len()];
+        let mut sides = vec![0; plants.len()];
+        for posn in coords(bounds) {
+            let id = labels[idx(posn)];
+            let same = |p: Option<Posn>| p.is_some_and(|p| labels[idx(p)] == id);
+            areas[id] += 1;
+            perimeters[id] += DIRS
+                .iter()
+                .filter(|d| !same(d.step_bounded(posn, bounds)))
+                .count();
+            sides[id] += DIRS
+                .iter()
+                .filter(|d| {
+                    let ahead = d.step_bounded(posn, bounds);
+                    let beside = d.cw().step_bounded(posn, bounds);
+                    let diagonal = ahead.and_then(|p| d.cw().step_bounded(p, bounds));
+                    match (same(ahead), same(beside)) {
+                        (false, false) => true,
+                        (true, true) => !same(diagonal),
+                        _ => false,
+                    }
+                })
+                .count();
+        }
+        Self {
+            bounds,
+            labels,
+            plants,
+            members,
+            areas,
+            perimeters,
+            sides,
         }
     }
-
-    area * perim
+    pub fn len(&self) -> usize {
+        self.plants.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.plants.is_empty()
+    }
+    pub fn label(&self, posn: Posn) -> usize {
+        self.labels[posn.0 * self.bounds.1 + posn.1]
+    }
+    pub fn plant(&self, id: usize) -> u8 {
+        self.plants[id]
+    }
+    pub fn cells(&self, id: usize) -> &[Posn] {
+        &self.members[id]
+    }
+    pub fn area(&self, id: usize) -> usize {
+        self.areas[id]
+    }
+    pub fn perimeter(&self, id: usize) -> usize {
+        self.perimeters[id]
+    }
+    pub fn sides(&self, id: usize) -> usize {
+        self.sides[id]
+    }
+    // Every stretch of fence one plot long, keyed by the lattice point it
+    // starts at and the way it runs (keeping the region on the right), along
+    // with the plot it fences in
+    fn fences(&self, id: usize) -> BTreeMap<(Posn, Direction), Posn> {
+        let mut fences = BTreeMap::new();
+        for &(r, c) in self.cells(id) {
+            for d in DIRS {
+                if d.step_bounded((r, c), self.bounds)
+                    .is_some_and(|n| self.label(n) == id)
+                {
+                    continue;
+                }
+                let start = match d {
+                    Direction::Up => (r, c),
+                    Direction::Right => (r, c + 1),
+                    Direction::Down => (r + 1, c + 1),
+                    Direction::Left => (r + 1, c),
+                };
+                fences.insert((start, d.cw()), (r, c));
+            }
+        }
+        fences
+    }
+    // Walks each fence around, returning its corners and the plots just
+    // outside it. Turning towards the region wherever there's a choice keeps
+    // diagonal plots of the region apart (and so diagonal gaps together).
+    fn trace(&self, id: usize) -> Vec<(Vec<Posn>, Vec<Option<Posn>>)> {
+        let mut fences = self.fences(id);
+        let mut rings = vec![];
+        while let Some((first, _)) = fences.first_key_value() {
+            let first = *first;
+            let mut corners = vec![];
+            let mut outside = vec![];
+            let mut key = first;
+            let mut last = None;
+            loop {
+                let (vertex, dir) = key;
+                let plot = fences.remove(&key).unwrap();
+                outside.push(dir.ccw().step_bounded(plot, self.bounds));
+                if last != Some(dir) {
+                    corners.push(vertex);
+                }
+                last = Some(dir);
+                // Lattice points are never off the top or left of the garden
+                let next = dir.step(vertex).unwrap();
+                let Some(found) = [dir.cw(), dir, dir.ccw()]
+                    .map(|d| (next, d))
+                    .into_iter()
+                    .find(|k| *k == first || fences.contains_key(k))
+                else {
+                    unreachable!("Fence around region {id} isn't closed");
+                };
+                if found == first {
+                    break;
+                }
+                key = found;
+            }
+            // The walk may have started partway along a side
+            if last == Some(first.1) {
+                corners.remove(0);
+            }
+            rings.push((corners, outside));
+        }
+        rings
+    }
+    pub fn report(&self, id: usize) -> RegionReport {
+        let rings = self.trace(id);
+        let cells = self.cells(id);
+        let top_left = (
+            cells.iter().map(|p| p.0).min().unwrap(),
+            cells.iter().map(|p| p.1).min().unwrap(),
+        );
+        let bottom_right = (
+            cells.iter().map(|p| p.0).max().unwrap(),
+            cells.iter().map(|p| p.1).max().unwrap(),
+        );
+        // The top-left-most fence is always on the outside
+        let neighbors = rings[0]
+            .1
+            .iter()
+            .map(|p| p.map(|p| self.label(p)))
+            .collect::<Option<BTreeSet<_>>>();
+        let enclosed_by = match neighbors {
+            Some(n) if n.len() == 1 => n.first().copied(),
+            _ => None,
+        };
+        RegionReport {
+            id,
+            plant: self.plant(id),
+            area: self.area(id),
+            perimeter: self.perimeter(id),
+            sides: self.sides(id),
+            holes: rings.len() - 1,
+            bounding_box: (top_left, bottom_right),
+            enclosed_by,
+            outline: rings.into_iter().map(|(corners, _)| corners).collect(),
+            price: self.area(id) * self.perimeter(id),
+            bulk_price: self.area(id) * self.sides(id),
+        }
+    }
+    pub fn reports(&self) -> Vec<RegionReport> {
+        (0..self.len()).map(|id| self.report(id)).collect()
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone)]
@@ -166,27 +358,61 @@ fn cartograph(region: &BTreeSet<(usize, usize)>) -> usize {
 }
 
 #[aoc(day12, part1)]
-pub fn part1(garden: &HashMap<u8, BTreeSet<(usize, usize)>>) -> usize {
-    let mut garden_regions = vec![];
-    for posns in garden.values() {
-        let mut posns = posns.clone();
-        while let Some(curr) = posns.pop_first() {
-            garden_regions.push(explore(curr, &mut posns));
-        }
-    }
-
-    garden_regions.iter().map(compute).sum()
+pub fn part1(garden: &[Vec<u8>]) -> usize {
+    let regions = Regions::new(garden);
+    (0..regions.len())
+        .map(|id| regions.area(id) * regions.perimeter(id))
+        .sum()
 }
 
 #[aoc(day12, part2)]
-pub fn part2(garden: &HashMap<u8, BTreeSet<(usize, usize)>>) -> usize {
-    let mut garden_regions = vec![];
-    for posns in garden.values() {
-        let mut posns = posns.clone();
-        while let Some(curr) = posns.pop_first() {
-            garden_regions.push(explore(curr, &mut posns));
+pub fn part2(garden: &[Vec<u8>]) -> usize {
+    let regions = Regions::new(garden);
+    (0..regions.len())
+        .map(|id| regions.area(id) * regions.sides(id))
+        .sum()
+}
+
+#[aoc(day12, part2, wall_following)]
+pub fn part2_wall_following(garden: &[Vec<u8>]) -> usize {
+    let regions = Regions::new(garden);
+    (0..regions.len())
+        .map(|id| {
+            let region = regions.cells(id).iter().copied().collect();
+            cartograph(&region) * regions.area(id)
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLES: [(&str, usize, usize); 5] = [
+        ("AAAA\nBBCD\nBBCC\nEEEC", 140, 80),
+        ("OOOOO\nOXOXO\nOOOOO\nOXOXO\nOOOOO", 772, 436),
+        (
+            "RRRRIICCFF\nRRRRIICCCF\nVVRRRCCFFF\nVVRCCCJFFF\nVVVVCJJCFE\n\
+             VVIVCCJJEE\nVVIIICJJEE\nMIIIIIJJEE\nMIIISIJEEE\nMMMISSJEEE",
+            1930,
+            1206,
+        ),
+        ("EEEEE\nEXXXX\nEEEEE\nEXXXX\nEEEEE", 692, 236),
+        ("AAAAAA\nAAABBA\nAAABBA\nABBAAA\nABBAAA\nAAAAAA", 1184, 368),
+    ];
+
+    #[test]
+    fn corners_match_wall_following() {
+        for (garden, price, bulk_price) in EXAMPLES {
+            let garden = gen(garden);
+            let regions = Regions::new(&garden);
+            for id in 0..regions.len() {
+                let region = regions.cells(id).iter().copied().collect();
+                assert_eq!(regions.sides(id), cartograph(&region));
+            }
+            assert_eq!(part1(&garden), price);
+            assert_eq!(part2(&garden), bulk_price);
+            assert_eq!(part2_wall_following(&garden), bulk_price);
         }
     }
-
-    garden_regions.iter().map(|s| cartograph(s) * s.len()).sum()
 }
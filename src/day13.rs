@@ -1,4 +1,5 @@
 use aoc_runner_derive::{aoc, aoc_generator};
+#[cfg(feature = "mathru")]
 use mathru::{
     algebra::linear::{
         matrix::{General, Solve},
@@ -10,86 +11,492 @@ use regex::Regex;
 
 use crate::util::Point;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Default, Hash)]
 pub struct Claw {
-    a: Point<u64>,
-    b: Point<u64>,
+    // In the order they're listed
+    buttons: Vec<Point<u64>>,
     prize: Point<u64>,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ClawRules {
+    // Tokens per press of each button, in the same order as the buttons
+    pub costs: Vec<u64>,
+    // Tokens per press of any button past the ones in `costs`
+    pub extra_cost: u64,
+    pub max_presses: Option<u64>,
+    // Added to both coordinates of every prize
+    pub prize_offset: u64,
+}
+
+impl ClawRules {
+    pub fn cost(&self, button: usize) -> u64 {
+        self.costs.get(button).copied().unwrap_or(self.extra_cost)
+    }
+}
+
+impl Default for ClawRules {
+    fn default() -> Self {
+        Self {
+            costs: vec![3, 1],
+            extra_cost: 1,
+            max_presses: Some(100),
+            prize_offset: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Unwinnable {
+    // Including when there's no solution at all
+    NoIntegerSolution,
+    NegativePresses,
+    OverLimit,
+    // The presses, or what they'd cost, are too big to count
+    Overflow,
+    // Too many buttons to solve exactly, and too many ways to press them to
+    // try them all
+    SearchTooLarge,
+}
+
+// How many press counts the search for four or more buttons may try
+const SEARCH_LIMIT: i128 = 10_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Win {
+    pub presses: Vec<u64>,
+    pub cost: u64,
+}
+
+type Vec2 = (i128, i128);
+
+// Every step from here on is checked, since the buttons and prize can be big
+// enough for the products to overflow even an i128
+fn det(a: Vec2, b: Vec2) -> Result<i128, Unwinnable> {
+    a.0.checked_mul(b.1)
+        .zip(b.0.checked_mul(a.1))
+        .and_then(|(l, r)| l.checked_sub(r))
+        .ok_or(Unwinnable::Overflow)
+}
+
+// Each `base + r*slope`
+fn along<const N: usize>(lines: [Vec2; N], r: i128) -> Result<[i128; N], Unwinnable> {
+    let mut out = [0; N];
+    for (o, (base, slope)) in out.iter_mut().zip(lines) {
+        *o = slope
+            .checked_mul(r)
+            .and_then(|s| base.checked_add(s))
+            .ok_or(Unwinnable::Overflow)?;
+    }
+    Ok(out)
+}
+
+// Returns (g, x, y) such that a*x + b*y = g = gcd(a, b)
+fn ext_gcd(a: i128, b: i128) -> (i128, i128, i128) {
+    if b == 0 {
+        (a, 1, 0)
+    } else {
+        let (g, x, y) = ext_gcd(b, a % b);
+        (g, y, x - (a / b) * y)
+    }
+}
+
+fn ceil_div(n: i128, d: i128) -> i128 {
+    n.div_euclid(d) + i128::from(n.rem_euclid(d) != 0)
+}
+
+// Every k with a*k = b (mod m), as k0 + step*r
+fn congruence(a: i128, b: i128, m: i128) -> Result<(i128, i128), Unwinnable> {
+    let (g, x, _) = ext_gcd(a.rem_euclid(m), m);
+    let b = b.rem_euclid(m);
+    if b % g != 0 {
+        return Err(Unwinnable::NoIntegerSolution);
+    }
+    let step = m / g;
+    let k0 = x.checked_mul(b / g).ok_or(Unwinnable::Overflow)?;
+    Ok((k0.rem_euclid(step), step))
+}
+
+// Whether some integer combination of the buttons (negative presses allowed)
+// reaches the target. The buttons generate the same lattice as (g, h) and
+// (0, z), which is easy to test against.
+fn in_lattice(buttons: &[Vec2], target: Vec2) -> Result<bool, Unwinnable> {
+    let (mut g, mut h, mut z) = (0i128, 0i128, 0i128);
+    for &(x, y) in buttons {
+        let (d, s, t) = ext_gcd(g, x);
+        if d == 0 {
+            z = ext_gcd(z, y.abs()).0;
+            continue;
+        }
+        let w = (x / d)
+            .checked_mul(h)
+            .zip((g / d).checked_mul(y))
+            .and_then(|(l, r)| l.checked_sub(r)?.checked_abs())
+            .ok_or(Unwinnable::Overflow)?;
+        z = ext_gcd(z, w).0;
+        h = s
+            .checked_mul(h)
+            .zip(t.checked_mul(y))
+            .and_then(|(l, r)| l.checked_add(r))
+            .ok_or(Unwinnable::Overflow)?;
+        g = d;
+    }
+    let rest = match g {
+        0 if target.0 != 0 => return Ok(false),
+        0 => target.1,
+        g if target.0 % g != 0 => return Ok(false),
+        g => (target.0 / g)
+            .checked_mul(h)
+            .and_then(|n| target.1.checked_sub(n))
+            .ok_or(Unwinnable::Overflow)?,
+    };
+    Ok(if z == 0 { rest == 0 } else { rest % z == 0 })
+}
+
+// The cheapest r that keeps every `base + r*slope` between 0 and the limit,
+// if there is one
+fn pick(lines: &[Vec2], costs: &[i128], max: Option<i128>) -> Result<i128, Unwinnable> {
+    let (mut lo, mut hi) = (i128::MIN, i128::MAX);
+    // First without the limit, to tell negative presses apart from too many
+    let overflow = || Unwinnable::Overflow;
+    for max in [None, max] {
+        for &(base, slope) in lines {
+            match slope.signum() {
+                0 if base < 0 => return Err(Unwinnable::NegativePresses),
+                0 if max.is_some_and(|m| base > m) => return Err(Unwinnable::OverLimit),
+                0 => {}
+                1 => {
+                    lo = lo.max(ceil_div(base.checked_neg().ok_or_else(overflow)?, slope));
+                    if let Some(m) = max {
+                        let room = m.checked_sub(base).ok_or_else(overflow)?;
+                        hi = hi.min(room.div_euclid(slope));
+                    }
+                }
+                _ => {
+                    let slope = slope.checked_neg().ok_or_else(overflow)?;
+                    hi = hi.min(base.div_euclid(slope));
+                    if let Some(m) = max {
+                        lo = lo.max(ceil_div(base.checked_sub(m).ok_or_else(overflow)?, slope));
+                    }
+                }
+            }
+        }
+        if lo > hi {
+            return Err(match max {
+                None => Unwinnable::NegativePresses,
+                Some(_) => Unwinnable::OverLimit,
+            });
+        }
+    }
+    let slope = lines
+        .iter()
+        .zip(costs)
+        .try_fold(0i128, |acc, (l, c)| acc.checked_add(l.1.checked_mul(*c)?))
+        .ok_or(Unwinnable::Overflow)?;
+    Ok(if slope >= 0 { lo } else { hi })
+}
+
+// Cramer's rule, done exactly. If both buttons move the claw along the same
+// line, there may be many ways to the prize, so take the cheapest.
+fn pair(
+    a: Vec2,
+    b: Vec2,
+    t: Vec2,
+    costs: [i128; 2],
+    max: Option<i128>,
+) -> Result<[i128; 2], Unwinnable> {
+    let d = det(a, b)?;
+    let presses = if d != 0 {
+        let (na, nb) = (det(t, b)?, det(a, t)?);
+        // Only i128::MIN / -1 doesn't fit
+        let (Some(ra), Some(rb)) = (na.checked_rem(d), nb.checked_rem(d)) else {
+            return Err(Unwinnable::Overflow);
+        };
+        if ra != 0 || rb != 0 {
+            return Err(Unwinnable::NoIntegerSolution);
+        }
+        [na / d, nb / d]
+    } else if det(a, t)? != 0 || det(b, t)? != 0 {
+        // The buttons only move along one line, and the prize isn't on it
+        return Err(Unwinnable::NoIntegerSolution);
+    } else {
+        // Go by whichever coordinate actually moves
+        let (u, w, t) = if (a.0, b.0) != (0, 0) {
+            (a.0, b.0, t.0)
+        } else {
+            (a.1, b.1, t.1)
+        };
+        match (u, w) {
+            (0, 0) => [0, 0],
+            // A button that does nothing is never worth pressing
+            (0, w) if t % w == 0 => [0, t / w],
+            (u, 0) if t % u == 0 => [t / u, 0],
+            (0, _) | (_, 0) => return Err(Unwinnable::NoIntegerSolution),
+            (u, w) => {
+                let (g, x, y) = ext_gcd(u, w);
+                if t % g != 0 {
+                    return Err(Unwinnable::NoIntegerSolution);
+                }
+                // Every solution is (a0 + r*w/g, b0 - r*u/g) for some r
+                let (Some(a0), Some(b0)) = (x.checked_mul(t / g), y.checked_mul(t / g)) else {
+                    return Err(Unwinnable::Overflow);
+                };
+                let lines = [(a0, w / g), (b0, -u / g)];
+                along(lines, pick(&lines, &costs, max)?)?
+            }
+        }
+    };
+    // Neither button moving the claw at all is the one case not caught above
+    let reached = |u: i128, w: i128| {
+        u.checked_mul(presses[0])
+            .zip(w.checked_mul(presses[1]))
+            .and_then(|(l, r)| l.checked_add(r))
+            .ok_or(Unwinnable::Overflow)
+    };
+    if (reached(a.0, b.0)?, reached(a.1, b.1)?) != t {
+        return Err(Unwinnable::NoIntegerSolution);
+    }
+    // Nothing to choose between any more, just check it's allowed
+    pick(&presses.map(|p| (p, 0)), &costs, max)?;
+    Ok(presses)
+}
+
+// With three buttons, two of which aren't parallel, every solution is one
+// press count for the third button plus Cramer's rule for the other two. The
+// counts that divide evenly are an arithmetic sequence, so every press count
+// moves in step with a single r. None if all three are parallel.
+fn triple(
+    buttons: [Vec2; 3],
+    t: Vec2,
+    costs: [i128; 3],
+    max: Option<i128>,
+) -> Result<Option<[i128; 3]>, Unwinnable> {
+    let Some((i, j, l)) = [(0, 1, 2), (0, 2, 1), (1, 2, 0)]
+        .into_iter()
+        .find(|&(i, j, _)| det(buttons[i], buttons[j]) != Ok(0))
+    else {
+        return Ok(None);
+    };
+    let d = det(buttons[i], buttons[j])?;
+    // Cramer's rule on t - k*l gives (di - k*ei)/d and (dj - k*ej)/d
+    let (di, ei) = (det(t, buttons[j])?, det(buttons[l], buttons[j])?);
+    let (dj, ej) = (det(buttons[i], t)?, det(buttons[i], buttons[l])?);
+    let m = d.checked_abs().ok_or(Unwinnable::Overflow)?;
+    let (k0, s1) = congruence(ei, di, m)?;
+    let (Some(a), Some(b)) = (
+        ej.checked_mul(s1),
+        ej.checked_mul(k0).and_then(|n| dj.checked_sub(n)),
+    ) else {
+        return Err(Unwinnable::Overflow);
+    };
+    let (r0, s2) = congruence(a, b, m)?;
+    // The other two counts, as lines in r, given the third's
+    let cramer = |n: i128, e: i128, k0: i128, step: i128| -> Option<Vec2> {
+        let base = n.checked_sub(k0.checked_mul(e)?)?.checked_div(d)?;
+        let slope = step.checked_mul(e)?.checked_div(d)?.checked_neg()?;
+        Some((base, slope))
+    };
+    let lines = (|| {
+        let k0 = k0.checked_add(s1.checked_mul(r0)?)?;
+        let step = s1.checked_mul(s2)?;
+        let mut lines = [(0, 0); 3];
+        lines[l] = (k0, step);
+        lines[i] = cramer(di, ei, k0, step)?;
+        lines[j] = cramer(dj, ej, k0, step)?;
+        Some(lines)
+    })()
+    .ok_or(Unwinnable::Overflow)?;
+    along(lines, pick(&lines, &costs, max)?).map(Some)
+}
+
+// How many times the button can be pressed without overshooting the target
+fn reach(button: Vec2, t: Vec2, max: Option<i128>) -> i128 {
+    let reach = [(button.0, t.0), (button.1, t.1)]
+        .into_iter()
+        .filter(|&(step, _)| step > 0)
+        .map(|(step, t)| t / step)
+        .min()
+        .unwrap_or(0);
+    max.map_or(reach, |m| m.min(reach))
+}
+
+// The cheapest non-negative presses that reach the target. Two or three
+// buttons can be done exactly; past that, try every count for the buttons
+// after the third that doesn't overshoot, as long as there aren't too many.
+fn cheapest(
+    buttons: &[Vec2],
+    t: Vec2,
+    costs: &[i128],
+    max: Option<i128>,
+) -> Result<Vec<i128>, Unwinnable> {
+    match *buttons {
+        [] if t == (0, 0) => return Ok(vec![]),
+        [] => return Err(Unwinnable::NoIntegerSolution),
+        [a] => return pair(a, (0, 0), t, [costs[0], 0], max).map(|p| vec![p[0]]),
+        [a, b] => return pair(a, b, t, [costs[0], costs[1]], max).map(Vec::from),
+        [a, b, c] => {
+            if let Some(found) = triple([a, b, c], t, [costs[0], costs[1], costs[2]], max)? {
+                return Ok(Vec::from(found));
+            }
+        }
+        _ => {}
+    }
+    if !in_lattice(buttons, t)? {
+        return Err(Unwinnable::NoIntegerSolution);
+    }
+    // The first three buttons are left to the exact solver, or only the first
+    // two if those three are all parallel
+    let exact = if det(buttons[0], buttons[1])? != 0
+        || det(buttons[0], buttons[2])? != 0
+        || det(buttons[1], buttons[2])? != 0
+    {
+        3
+    } else {
+        2
+    };
+    let tries = buttons[exact..].iter().try_fold(1i128, |acc, &b| {
+        acc.checked_mul(reach(b, t, max) + 1)
+            .filter(|&n| n <= SEARCH_LIMIT)
+    });
+    if tries.is_none() {
+        return Err(Unwinnable::SearchTooLarge);
+    }
+    let (&last, rest) = buttons.split_last().unwrap();
+    let mut best: Option<(i128, Vec<i128>)> = None;
+    for k in 0..=reach(last, t, max) {
+        let left = (t.0 - k * last.0, t.1 - k * last.1);
+        let Ok(mut presses) = cheapest(rest, left, costs, max) else {
+            continue;
+        };
+        presses.push(k);
+        let cost = presses
+            .iter()
+            .zip(costs)
+            .try_fold(0i128, |acc, (p, c)| acc.checked_add(p.checked_mul(*c)?))
+            .ok_or(Unwinnable::Overflow)?;
+        if best.as_ref().is_none_or(|(b, _)| cost < *b) {
+            best = Some((cost, presses));
+        }
+    }
+    match best {
+        Some((_, presses)) => Ok(presses),
+        None if max.is_none() => Err(Unwinnable::NegativePresses),
+        // Nothing within the limit, so see if there's anything at all
+        None => match cheapest(buttons, t, costs, None) {
+            Ok(_) => Err(Unwinnable::OverLimit),
+            Err(e) => Err(e),
+        },
+    }
+}
+
 impl Claw {
-    pub fn solve(&self) -> Option<(u64, u64)> {
+    pub fn play(&self, rules: &ClawRules) -> Result<Win, Unwinnable> {
+        let buttons = self
+            .buttons
+            .iter()
+            .map(|b| (i128::from(b.x), i128::from(b.y)))
+            .collect::<Vec<_>>();
+        let offset = i128::from(rules.prize_offset);
+        let prize = (
+            i128::from(self.prize.x) + offset,
+            i128::from(self.prize.y) + offset,
+        );
+        let costs = (0..buttons.len())
+            .map(|b| i128::from(rules.cost(b)))
+            .collect::<Vec<_>>();
+        let max = rules.max_presses.map(i128::from);
+        let presses = cheapest(&buttons, prize, &costs, max)?
+            .into_iter()
+            .map(|p| p.try_into().map_err(|_| Unwinnable::Overflow))
+            .collect::<Result<Vec<u64>, _>>()?;
+        let cost = presses
+            .iter()
+            .enumerate()
+            .try_fold(0u64, |acc, (b, p)| {
+                acc.checked_add(p.checked_mul(rules.cost(b))?)
+            })
+            .ok_or(Unwinnable::Overflow)?;
+        Ok(Win { presses, cost })
+    }
+    pub fn solve(&self) -> Option<Vec<u64>> {
+        let rules = ClawRules {
+            max_presses: None,
+            ..Default::default()
+        };
+        self.play(&rules).ok().map(|w| w.presses)
+    }
+    #[cfg(feature = "mathru")]
+    pub fn solve_float(&self) -> Option<(u64, u64)> {
+        let [a, b] = self.buttons[..] else {
+            return None;
+        };
         // 1. Ax + Bx = px
         // 2. Ay + By = py
-        let a: General<f64> = matrix![
-            self.a.x as f64, self.b.x as f64;
-            self.a.y as f64, self.b.y as f64
+        let m: General<f64> = matrix![
+            a.x as f64, b.x as f64;
+            a.y as f64, b.y as f64
         ];
-        let b: Vector<f64> = vector![self.prize.x as f64; self.prize.y as f64];
-        let x = a.solve(&b).ok()?;
-        let (a, b) = (x[0].round() as u64, x[1].round() as u64);
+        let p: Vector<f64> = vector![self.prize.x as f64; self.prize.y as f64];
+        let x = m.solve(&p).ok()?;
+        let (na, nb) = (x[0].round() as u64, x[1].round() as u64);
         // Check that it works
-        if ((self.a * a) + (self.b * b)) != self.prize {
+        if ((a * na) + (b * nb)) != self.prize {
             None
         } else {
-            Some((a, b))
+            Some((na, nb))
         }
     }
 }
 
+// Machines are separated by blank lines, and can have any number of buttons
 #[aoc_generator(day13)]
 pub fn gen(input: &str) -> Vec<Claw> {
-    let button_re = Regex::new(r"^Button [AB]: X\+(\d+), Y\+(\d+)$").unwrap();
+    let button_re = Regex::new(r"^Button \w+: X\+(\d+), Y\+(\d+)$").unwrap();
     let prize_re = Regex::new(r"^Prize: X=(\d+), Y=(\d+)$").unwrap();
-    let lines = input.lines().chain(std::iter::once("")).collect::<Vec<_>>();
+    let point = |caps: regex::Captures| Point {
+        x: caps.get(1).unwrap().as_str().parse().unwrap(),
+        y: caps.get(2).unwrap().as_str().parse().unwrap(),
+    };
+    let lines = input.lines().collect::<Vec<_>>();
     lines
-        .chunks_exact(4)
+        .split(|l| l.trim().is_empty())
+        .filter(|claw| !claw.is_empty())
         .map(|claw| {
-            let a = button_re.captures(claw[0]).unwrap();
-            let a = Point {
-                x: a.get(1).unwrap().as_str().parse().unwrap(),
-                y: a.get(2).unwrap().as_str().parse().unwrap(),
-            };
-            let b = button_re.captures(claw[1]).unwrap();
-            let b = Point {
-                x: b.get(1).unwrap().as_str().parse().unwrap(),
-                y: b.get(2).unwrap().as_str().parse().unwrap(),
-            };
-            let prize = prize_re.captures(claw[2]).unwrap();
-            let prize = Point {
-                x: prize.get(1).unwrap().as_str().parse().unwrap(),
-                y: prize.get(2).unwrap().as_str().parse().unwrap(),
-            };
-
-            Claw { a, b, prize }
+            let (prize, buttons) = claw.split_last().unwrap();
+            let buttons = buttons
+                .iter()
+                .map(|b| point(button_re.captures(b).unwrap()))
+                .collect();
+            let prize = point(prize_re.captures(prize).unwrap());
+            Claw { buttons, prize }
         })
         .collect()
 }
 
+pub fn play_all(claws: &[Claw], rules: &ClawRules) -> Vec<Result<Win, Unwinnable>> {
+    use rayon::prelude::*;
+    claws.par_iter().map(|c| c.play(rules)).collect()
+}
+
 #[aoc(day13, part1)]
 pub fn part1(claws: &[Claw]) -> u64 {
-    use rayon::prelude::*;
-    claws
-        .par_iter()
-        .filter_map(|c| {
-            c.solve()
-                .filter(|&(a, b)| a <= 100 && b <= 100)
-                .map(|(a, b)| 3 * a + b)
-        })
+    play_all(claws, &ClawRules::default())
+        .into_iter()
+        .flatten()
+        .map(|w| w.cost)
         .sum()
 }
 
 #[aoc(day13, part2)]
 pub fn part2(claws: &[Claw]) -> u64 {
-    use rayon::prelude::*;
-    const FUDGE: u64 = 10000000000000;
-    claws
-        .par_iter()
-        .filter_map(|c| {
-            let mut c = *c;
-            c.prize.x += FUDGE;
-            c.prize.y += FUDGE;
-            c.solve().map(|(a, b)| 3 * a + b)
-        })
+    let rules = ClawRules {
+        max_presses: None,
+        prize_offset: 10000000000000,
+        ..Default::default()
+    };
+    play_all(claws, &rules)
+        .into_iter()
+        .flatten()
+        .map(|w| w.cost)
         .sum()
 }
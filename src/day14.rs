@@ -4,8 +4,20 @@ use aoc_runner_derive::{aoc, aoc_generator};
 
 use crate::util::Point;
 
-const ROOM_ROWS: isize = 103;
-const ROOM_COLS: isize = 101;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Room {
+    pub rows: isize,
+    pub cols: isize,
+}
+
+impl Default for Room {
+    fn default() -> Self {
+        Self {
+            rows: 103,
+            cols: 101,
+        }
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
 pub struct Robot {
@@ -14,20 +26,29 @@ pub struct Robot {
 }
 
 impl Robot {
-    pub fn step(&mut self) {
+    pub fn step(&mut self, room: &Room) {
+        *self = self.at(1, room);
+    }
+
+    // Where the robot is after `t` seconds (which can be negative). Each axis
+    // repeats once the robot has wrapped around the room, so `t` only
+    // matters modulo the room's size.
+    pub fn at(&self, t: isize, room: &Room) -> Self {
         let Point { x, y } = self.posn;
         let Point { x: vx, y: vy } = self.velocity;
-
-        self.posn = Point {
-            x: (x + vx).rem_euclid(ROOM_COLS),
-            y: (y + vy).rem_euclid(ROOM_ROWS),
-        };
+        Self {
+            posn: Point {
+                x: (x + vx * t.rem_euclid(room.cols)).rem_euclid(room.cols),
+                y: (y + vy * t.rem_euclid(room.rows)).rem_euclid(room.rows),
+            },
+            velocity: self.velocity,
+        }
     }
 
-    pub fn quadrant(&self) -> Option<usize> {
+    pub fn quadrant(&self, room: &Room) -> Option<usize> {
         // if I'm exactly in the middle, nope
-        let midx = (ROOM_COLS - 1) / 2;
-        let midy = (ROOM_ROWS - 1) / 2;
+        let midx = (room.cols - 1) / 2;
+        let midy = (room.rows - 1) / 2;
         if self.posn.x == midx || self.posn.y == midy {
             None
         } else {
@@ -63,37 +84,36 @@ pub fn gen(input: &str) -> Vec<Robot> {
         .collect()
 }
 
-#[aoc(day14, part1)]
-pub fn part1(robots: &[Robot]) -> usize {
-    let mut robots = robots.to_vec();
-    for _ in 0..100 {
-        for r in &mut robots {
-            r.step();
-        }
-    }
+pub fn safety_factor(robots: &[Robot], room: &Room, t: isize) -> usize {
     let mut quads = [0, 0, 0, 0];
-    for r in &robots {
-        if let Some(q) = r.quadrant() {
+    for r in robots {
+        if let Some(q) = r.at(t, room).quadrant(room) {
             quads[q] += 1;
         }
     }
     quads.iter().product()
 }
 
+#[aoc(day14, part1)]
+pub fn part1(robots: &[Robot]) -> usize {
+    safety_factor(robots, &Room::default(), 100)
+}
+
 #[aoc(day14, part2)]
 pub fn part2(robots: &[Robot]) -> usize {
+    let room = Room::default();
     let mut robots = robots.to_vec();
     for t in 1.. {
         for r in &mut robots {
-            r.step();
+            r.step(&room);
         }
         let posns: HashSet<(isize, isize)> = robots.iter().map(|r| (r.posn.x, r.posn.y)).collect();
         if posns.len() == robots.len() {
             println!();
             println!("t: {t}");
-            for r in 0..ROOM_ROWS {
-                for c in 0..ROOM_COLS {
-                    if posns.contains(&(r, c)) {
+            for r in 0..room.rows {
+                for c in 0..room.cols {
+                    if posns.contains(&(c, r)) {
                         print!("▮");
                     } else {
                         print!(" ");
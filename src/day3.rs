@@ -1,204 +1,158 @@
+use std::ops::RangeInclusive;
+
 use aoc_runner_derive::aoc;
 use regex::Regex;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Step {
-    Start,
-    M,
-    U,
-    L,
-    LeftParenMul,
-    LeftDigit,
-    Comma,
-    RightDigit,
-    D,
-    O,
-    N,
-    Apostrophe,
-    T,
-    LeftParenEnable,
-    LeftParenDisable,
+pub enum Effect {
+    Product,
+    Sum,
+    Enable,
+    Disable,
+    Toggle,
 }
-#[derive(Debug, Clone)]
-struct State {
-    step: Step,
-    left: String,
-    right: String,
-    enable: bool,
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub name: &'static str,
+    pub arity: usize,
+    pub digits: RangeInclusive<usize>,
+    pub effect: Effect,
 }
 
-impl State {
-    fn reset(&mut self) {
-        self.step = Step::Start;
-        self.left = String::new();
-        self.right = String::new();
+impl Instruction {
+    pub const fn new(name: &'static str, arity: usize, effect: Effect) -> Self {
+        Self {
+            name,
+            arity,
+            digits: 1..=3,
+            effect,
+        }
     }
-    fn blind_next(&mut self, next: char) -> Option<u32> {
-        match (self.step, next) {
-            (Step::Start, 'm') if self.enable => {
-                self.step = Step::M;
-            }
-            (Step::M, 'u') if self.enable => {
-                self.step = Step::U;
-            }
-            (Step::U, 'l') if self.enable => {
-                self.step = Step::L;
-            }
-            (Step::L, '(') if self.enable => {
-                self.step = Step::LeftParenMul;
-            }
-            (Step::LeftDigit, ',') if self.enable => {
-                self.step = Step::Comma;
-            }
-            (Step::LeftParenMul, c) | (Step::LeftDigit, c) if self.enable => {
-                if !c.is_numeric() {
-                    // Bad!
-                    self.reset();
-                    self.next(c);
-                } else {
-                    self.step = Step::LeftDigit;
-                    self.left.push(c);
-                }
-            }
-            (Step::RightDigit, ')') if self.enable => {
-                // Done! Calculate and reset
-                let left: u32 = self.left.parse().unwrap();
-                let right: u32 = self.right.parse().unwrap();
-
-                // Reset
-                self.reset();
-                return Some(left * right);
-            }
-            (Step::Comma, c) | (Step::RightDigit, c) if self.enable => {
-                if !c.is_numeric() {
-                    self.reset();
-                    self.next(c);
-                } else {
-                    self.step = Step::RightDigit;
-                    self.right.push(c);
+    pub fn with_digits(mut self, digits: RangeInclusive<usize>) -> Self {
+        self.digits = digits;
+        self
+    }
+    // Try to match this instruction at the start of `input`; on success,
+    // returns the operands and how many bytes were consumed
+    fn parse(&self, input: &[u8]) -> Option<(Vec<u64>, usize)> {
+        let mut idx = self.name.len();
+        if !input.starts_with(self.name.as_bytes()) || input.get(idx) != Some(&b'(') {
+            return None;
+        }
+        idx += 1;
+        let mut operands = Vec::with_capacity(self.arity);
+        for o in 0..self.arity {
+            if o != 0 {
+                if input.get(idx) != Some(&b',') {
+                    return None;
                 }
-            }
-            (s, c) if s != Step::Start => {
-                self.reset();
-                self.next(c);
-            }
-            _ => self.reset(),
+                idx += 1;
+            }
+            let len = input[idx..]
+                .iter()
+                .take_while(|b| b.is_ascii_digit())
+                .count();
+            if !self.digits.contains(&len) {
+                return None;
+            }
+            // Every byte is an ASCII digit, so this can only fail on overflow
+            let num = std::str::from_utf8(&input[idx..idx + len])
+                .unwrap()
+                .parse()
+                .ok()?;
+            operands.push(num);
+            idx += len;
         }
-        None
+        if input.get(idx) != Some(&b')') {
+            return None;
+        }
+        Some((operands, idx + 1))
     }
-    fn next(&mut self, next: char) -> Option<u32> {
-        match (self.step, next) {
-            (Step::Start, 'm') if self.enable => {
-                self.step = Step::M;
-            }
-            (Step::Start, 'd') => {
-                self.step = Step::D;
-            }
-            (Step::M, 'u') if self.enable => {
-                self.step = Step::U;
-            }
-            (Step::U, 'l') if self.enable => {
-                self.step = Step::L;
-            }
-            (Step::L, '(') if self.enable => {
-                self.step = Step::LeftParenMul;
-            }
-            (Step::LeftDigit, ',') if self.enable => {
-                self.step = Step::Comma;
-            }
-            (Step::LeftParenMul, c) | (Step::LeftDigit, c) if self.enable => {
-                if !c.is_numeric() {
-                    // Bad!
-                    self.reset();
-                    self.next(c);
-                } else {
-                    self.step = Step::LeftDigit;
-                    self.left.push(c);
-                }
-            }
-            (Step::RightDigit, ')') if self.enable => {
-                // Done! Calculate and reset
-                let left: u32 = self.left.parse().unwrap();
-                let right: u32 = self.right.parse().unwrap();
+}
 
-                // Reset
-                self.reset();
-                return Some(left * right);
-            }
-            (Step::Comma, c) | (Step::RightDigit, c) if self.enable => {
-                if !c.is_numeric() {
-                    self.reset();
-                    self.next(c);
-                } else {
-                    self.step = Step::RightDigit;
-                    self.right.push(c);
+#[derive(Debug, Clone)]
+pub struct Machine {
+    instructions: Vec<Instruction>,
+    enable: bool,
+}
+
+impl Machine {
+    pub fn new(instructions: Vec<Instruction>) -> Self {
+        Self {
+            instructions,
+            enable: true,
+        }
+    }
+    fn apply(&mut self, effect: Effect, operands: &[u64]) -> Option<Option<u64>> {
+        match effect {
+            Effect::Enable => self.enable = true,
+            Effect::Disable => self.enable = false,
+            Effect::Toggle => self.enable = !self.enable,
+            Effect::Product if self.enable => {
+                return operands
+                    .iter()
+                    .try_fold(1u64, |acc, &o| acc.checked_mul(o))
+                    .map(Some);
+            }
+            Effect::Sum if self.enable => {
+                return operands
+                    .iter()
+                    .try_fold(0u64, |acc, &o| acc.checked_add(o))
+                    .map(Some);
+            }
+            Effect::Product | Effect::Sum => {}
+        }
+        Some(None)
+    }
+    // Returns None if the total (or any single instruction) overflows
+    pub fn run(&mut self, input: &str) -> Option<u64> {
+        let input = input.as_bytes();
+        let mut total = 0u64;
+        let mut idx = 0;
+        while idx < input.len() {
+            let found = self.instructions.iter().find_map(|i| {
+                i.parse(&input[idx..])
+                    .map(|(ops, len)| (i.effect, ops, len))
+            });
+            if let Some((effect, operands, len)) = found {
+                if let Some(value) = self.apply(effect, &operands)? {
+                    total = total.checked_add(value)?;
                 }
+                idx += len;
+            } else {
+                idx += 1;
             }
-            (Step::D, 'o') => {
-                self.step = Step::O;
-            }
-            (Step::O, '(') => {
-                self.step = Step::LeftParenEnable;
-            }
-            (Step::O, 'n') => {
-                self.step = Step::N;
-            }
-            (Step::N, '\'') => {
-                self.step = Step::Apostrophe;
-            }
-            (Step::Apostrophe, 't') => {
-                self.step = Step::T;
-            }
-            (Step::T, '(') => {
-                self.step = Step::LeftParenDisable;
-            }
-            (Step::LeftParenEnable, ')') => {
-                self.enable = true;
-                self.reset();
-            }
-            (Step::LeftParenDisable, ')') => {
-                self.enable = false;
-                self.reset();
-            }
-            (s, c) if s != Step::Start => {
-                self.reset();
-                self.next(c);
-            }
-            _ => self.reset(),
         }
-        None
+        Some(total)
     }
 }
 
 #[aoc(day3, part1)]
-pub fn part1(input: &str) -> u32 {
-    let mut state = State {
-        step: Step::Start,
-        left: String::new(),
-        right: String::new(),
-        enable: true,
-    };
-    input.chars().filter_map(|c| state.blind_next(c)).sum()
+pub fn part1(input: &str) -> u64 {
+    Machine::new(vec![Instruction::new("mul", 2, Effect::Product)])
+        .run(input)
+        .expect("Total overflowed")
 }
 
 #[aoc(day3, part1, regex)]
-pub fn part1_regex(input: &str) -> u32 {
-    let pat = Regex::new(r"mul\((\d+),(\d+)\)").unwrap();
+pub fn part1_regex(input: &str) -> u64 {
+    let pat = Regex::new(r"mul\((\d{1,3}),(\d{1,3})\)").unwrap();
     pat.captures_iter(input)
         .map(|c| {
-            let left: u32 = c.get(1).unwrap().as_str().parse().unwrap();
-            let right: u32 = c.get(2).unwrap().as_str().parse().unwrap();
+            let left: u64 = c.get(1).unwrap().as_str().parse().unwrap();
+            let right: u64 = c.get(2).unwrap().as_str().parse().unwrap();
             left * right
         })
         .sum()
 }
 #[aoc(day3, part2)]
-pub fn part2(input: &str) -> u32 {
-    let mut state = State {
-        step: Step::Start,
-        left: String::new(),
-        right: String::new(),
-        enable: true,
-    };
-    input.chars().filter_map(|c| state.next(c)).sum()
+pub fn part2(input: &str) -> u64 {
+    Machine::new(vec![
+        Instruction::new("mul", 2, Effect::Product),
+        Instruction::new("do", 0, Effect::Enable),
+        Instruction::new("don't", 0, Effect::Disable),
+    ])
+    .run(input)
+    .expect("Total overflowed")
 }
@@ -1,142 +1,199 @@
+use std::collections::HashSet;
+
 use aoc_runner_derive::{aoc, aoc_generator};
+use rayon::prelude::*;
 
-const WORD: [char; 4] = ['X', 'M', 'A', 'S'];
-const WORD_R: [char; 4] = ['S', 'A', 'M', 'X'];
+use crate::util::{Heading, HEADINGS};
 
-const MAS: [char; 3] = ['M', 'A', 'S'];
-const MAS_R: [char; 3] = ['S', 'A', 'M'];
+const X_MAS: &str = "M.S\n.A.\nM.S";
 
-#[aoc_generator(day4)]
-pub fn generate(input: &str) -> Vec<Vec<char>> {
-    input.lines().map(|l| l.chars().collect()).collect()
+// One of each pair of opposite headings; reading a palindrome along the
+// other one would find the same letters again
+fn is_forward(heading: &Heading) -> bool {
+    matches!(
+        heading,
+        Heading::East | Heading::SouthEast | Heading::South | Heading::SouthWest
+    )
 }
 
-#[aoc(day4, part1)]
-pub fn part1(lines: &[Vec<char>]) -> usize {
-    // Start horizontal
-    let mut count = 0;
-    for line in lines {
-        for w in line.windows(WORD.len()) {
-            if w == WORD || w == WORD_R {
-                count += 1;
-            }
-        }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Match {
+    pub start: (usize, usize),
+    pub direction: Heading,
+}
+
+fn matches_at(grid: &[Vec<char>], word: &[char], start: (usize, usize), dir: Heading) -> bool {
+    let (dr, dc) = dir.delta();
+    word.iter().enumerate().all(|(i, ch)| {
+        let r = start.0 as isize + dr * i as isize;
+        let c = start.1 as isize + dc * i as isize;
+        r >= 0 && c >= 0 && grid.get(r as usize).and_then(|l| l.get(c as usize)) == Some(ch)
+    })
+}
+
+fn headings(word: &[char]) -> Vec<Heading> {
+    match word.len() {
+        0 => vec![],
+        1 => vec![Heading::East],
+        _ if word.iter().eq(word.iter().rev()) => HEADINGS.into_iter().filter(is_forward).collect(),
+        _ => HEADINGS.to_vec(),
     }
-    // Vertical
-    for c in 0..lines[0].len() {
-        // Create the column of chars
-        let vert = lines.iter().map(|l| l[c]).collect::<Vec<_>>();
-        for w in vert.windows(WORD.len()) {
-            if w == WORD || w == WORD_R {
-                count += 1;
-            }
-        }
+}
+
+fn find_in_row(grid: &[Vec<char>], word: &[char], headings: &[Heading], r: usize) -> Vec<Match> {
+    (0..grid[r].len())
+        .filter(|&c| grid[r][c] == word[0])
+        .flat_map(|c| {
+            headings
+                .iter()
+                .filter(move |&&d| matches_at(grid, word, (r, c), d))
+                .map(move |&direction| Match {
+                    start: (r, c),
+                    direction,
+                })
+        })
+        .collect()
+}
+
+pub fn find_word(grid: &[Vec<char>], word: &str) -> Vec<Match> {
+    let word = word.chars().collect::<Vec<_>>();
+    let headings = headings(&word);
+    if headings.is_empty() {
+        return vec![];
     }
-    // Diagonals
-    for r in 0..lines.len() {
-        for c in 0..lines[0].len() {
-            // Get next four characters down and to the right, if I can
-            if r <= (lines.len() - WORD.len()) && c <= (lines[0].len() - WORD.len()) {
-                let c1 = lines[r][c];
-                let c2 = lines[r + 1][c + 1];
-                let c3 = lines[r + 2][c + 2];
-                let c4 = lines[r + 3][c + 3];
-                let tmp = [c1, c2, c3, c4];
-                if tmp == WORD || tmp == WORD_R {
-                    count += 1;
-                }
-            }
-            // Get next four characters down and to the left, if I can
-            if r <= (lines.len() - WORD.len()) && c >= (WORD.len() - 1) {
-                let c1 = lines[r][c];
-                let c2 = lines[r + 1][c - 1];
-                let c3 = lines[r + 2][c - 2];
-                let c4 = lines[r + 3][c - 3];
-                let tmp = [c1, c2, c3, c4];
-                if tmp == WORD || tmp == WORD_R {
-                    count += 1;
+    (0..grid.len())
+        .flat_map(|r| find_in_row(grid, &word, &headings, r))
+        .collect()
+}
+
+pub fn par_find_word(grid: &[Vec<char>], word: &str) -> Vec<Match> {
+    let word = word.chars().collect::<Vec<_>>();
+    let headings = headings(&word);
+    if headings.is_empty() {
+        return vec![];
+    }
+    (0..grid.len())
+        .into_par_iter()
+        .flat_map_iter(|r| find_in_row(grid, &word, &headings, r))
+        .collect()
+}
+
+// A 2D pattern where `.` matches any character
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Template {
+    cells: Vec<Vec<Option<char>>>,
+}
+
+impl From<&str> for Template {
+    fn from(value: &str) -> Self {
+        let width = value.lines().map(|l| l.chars().count()).max().unwrap_or(0);
+        let cells = value
+            .lines()
+            .map(|l| {
+                let mut row = l
+                    .chars()
+                    .map(|ch| if ch == '.' { None } else { Some(ch) })
+                    .collect::<Vec<_>>();
+                row.resize(width, None);
+                row
+            })
+            .collect();
+        Self { cells }
+    }
+}
+
+impl Template {
+    pub fn rows(&self) -> usize {
+        self.cells.len()
+    }
+    pub fn cols(&self) -> usize {
+        self.cells.first().map_or(0, Vec::len)
+    }
+    // Quarter turn clockwise
+    fn rotate(&self) -> Self {
+        let cells = (0..self.cols())
+            .map(|r| {
+                (0..self.rows())
+                    .map(|c| self.cells[self.rows() - 1 - c][r])
+                    .collect()
+            })
+            .collect();
+        Self { cells }
+    }
+    // Mirror left to right
+    fn reflect(&self) -> Self {
+        let cells = self
+            .cells
+            .iter()
+            .map(|row| row.iter().rev().copied().collect())
+            .collect();
+        Self { cells }
+    }
+    // Every distinct rotation and reflection of this template
+    pub fn variants(&self) -> Vec<Self> {
+        let mut seen = HashSet::new();
+        let mut variants = vec![];
+        let mut current = self.clone();
+        for _ in 0..4 {
+            for t in [current.clone(), current.reflect()] {
+                if seen.insert(t.clone()) {
+                    variants.push(t);
                 }
             }
+            current = current.rotate();
         }
+        variants
+    }
+    fn matches_at(&self, grid: &[Vec<char>], anchor: (usize, usize)) -> bool {
+        self.cells.iter().enumerate().all(|(r, row)| {
+            row.iter().enumerate().all(|(c, cell)| {
+                let found = grid.get(anchor.0 + r).and_then(|l| l.get(anchor.1 + c));
+                found.is_some() && cell.as_ref().is_none_or(|ch| found == Some(ch))
+            })
+        })
     }
-    count
 }
 
-#[aoc(day4, part1, rayon)]
-pub fn part1_rayon(lines: &[Vec<char>]) -> usize {
-    use rayon::prelude::*;
-    // Start horizontal
-    let mut count = lines
-        .par_iter()
-        .map(|line| {
-            line.windows(WORD.len())
-                .filter(|w| w == &WORD || w == &WORD_R)
-                .count()
-        })
-        .sum();
-    // Vertical
-    for c in 0..lines[0].len() {
-        // Create the column of chars
-        let vert = lines.iter().map(|l| l[c]).collect::<Vec<_>>();
-        for w in vert.windows(WORD.len()) {
-            if w == WORD || w == WORD_R {
-                count += 1;
-            }
+// Every placement of any orientation of the template, as its top-left corner
+// and which of `template.variants()` it is
+pub fn find_pattern(grid: &[Vec<char>], template: &Template) -> Vec<((usize, usize), usize)> {
+    let mut placements = vec![];
+    for (i, variant) in template.variants().iter().enumerate() {
+        if variant.rows() == 0 || variant.rows() > grid.len() {
+            continue;
         }
-    }
-    // Diagonals
-    for r in 0..lines.len() {
-        for c in 0..lines[0].len() {
-            // Get next four characters down and to the right, if I can
-            if r <= (lines.len() - WORD.len()) && c <= (lines[0].len() - WORD.len()) {
-                let c1 = lines[r][c];
-                let c2 = lines[r + 1][c + 1];
-                let c3 = lines[r + 2][c + 2];
-                let c4 = lines[r + 3][c + 3];
-                let tmp = [c1, c2, c3, c4];
-                if tmp == WORD || tmp == WORD_R {
-                    count += 1;
-                }
+        for r in 0..=(grid.len() - variant.rows()) {
+            let cols = grid[r].len();
+            if variant.cols() > cols {
+                continue;
             }
-            // Get next four characters down and to the left, if I can
-            if r <= (lines.len() - WORD.len()) && c >= (WORD.len() - 1) {
-                let c1 = lines[r][c];
-                let c2 = lines[r + 1][c - 1];
-                let c3 = lines[r + 2][c - 2];
-                let c4 = lines[r + 3][c - 3];
-                let tmp = [c1, c2, c3, c4];
-                if tmp == WORD || tmp == WORD_R {
-                    count += 1;
+            for c in 0..=(cols - variant.cols()) {
+                if variant.matches_at(grid, (r, c)) {
+                    placements.push(((r, c), i));
                 }
             }
         }
     }
-    count
+    placements.sort();
+    placements
+}
+
+#[aoc_generator(day4)]
+pub fn generate(input: &str) -> Vec<Vec<char>> {
+    input.lines().map(|l| l.chars().collect()).collect()
+}
+
+#[aoc(day4, part1)]
+pub fn part1(lines: &[Vec<char>]) -> usize {
+    find_word(lines, "XMAS").len()
+}
+
+#[aoc(day4, part1, rayon)]
+pub fn part1_rayon(lines: &[Vec<char>]) -> usize {
+    par_find_word(lines, "XMAS").len()
 }
 
 #[aoc(day4, part2)]
 pub fn part2(lines: &[Vec<char>]) -> usize {
-    let mut count = 0;
-    // Diagonals
-    for r in 0..lines.len() {
-        for c in 0..lines[0].len() {
-            // Get next three characters down and to the right, if I can
-            if r <= (lines.len() - MAS.len()) && c <= (lines[0].len() - MAS.len()) {
-                let c1 = lines[r][c];
-                let c2 = lines[r + 1][c + 1];
-                let c3 = lines[r + 2][c + 2];
-                let tmp = [c1, c2, c3];
-                if tmp == MAS || tmp == MAS_R {
-                    let c1 = lines[r][c + 2];
-                    let c2 = lines[r + 1][c + 1];
-                    let c3 = lines[r + 2][c];
-                    let tmp = [c1, c2, c3];
-                    if tmp == MAS || tmp == MAS_R {
-                        count += 1;
-                    }
-                }
-            }
-        }
-    }
-    count
+    find_pattern(lines, &Template::from(X_MAS)).len()
 }
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 
 use aoc_runner_derive::{aoc, aoc_generator};
+use petgraph::prelude::*;
 
 #[derive(Debug, Clone, Hash, Default)]
 pub struct Rules {
@@ -51,6 +52,116 @@ fn is_valid(pages: &[u32], rulebook: &HashMap<u32, Rules>) -> Result<(), (usize,
     Ok(())
 }
 
+// A broken `before|after` rule, along with where each page sits in the update
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Violation {
+    pub before: u32,
+    pub after: u32,
+    pub before_pos: usize,
+    pub after_pos: usize,
+}
+
+pub fn violations(pages: &[u32], rulebook: &HashMap<u32, Rules>) -> Vec<Violation> {
+    let mut found = vec![];
+    for (after_pos, after) in pages.iter().enumerate() {
+        let Some(rules) = rulebook.get(after) else {
+            continue;
+        };
+        for (before_pos, before) in pages.iter().enumerate().skip(after_pos + 1) {
+            if rules.comes_after.contains(before) {
+                found.push(Violation {
+                    before: *before,
+                    after: *after,
+                    before_pos,
+                    after_pos,
+                });
+            }
+        }
+    }
+    found
+}
+
+// Every rule broken by at least one update, most frequently broken first
+pub fn violation_summary(
+    rulebook: &HashMap<u32, Rules>,
+    manuals: &[Vec<u32>],
+) -> Vec<((u32, u32), usize)> {
+    let mut counts: HashMap<(u32, u32), usize> = HashMap::new();
+    for v in manuals.iter().flat_map(|pages| violations(pages, rulebook)) {
+        *counts.entry((v.before, v.after)).or_default() += 1;
+    }
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_by(|(r1, c1), (r2, c2)| c2.cmp(c1).then(r1.cmp(r2)));
+    counts
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RuleGraph {
+    graph: DiGraph<u32, ()>,
+    nodes: HashMap<u32, NodeIndex>,
+}
+
+impl RuleGraph {
+    pub fn new(rulebook: &HashMap<u32, Rules>) -> Self {
+        let mut graph = DiGraph::new();
+        let nodes: HashMap<u32, NodeIndex> = rulebook
+            .keys()
+            .map(|&page| (page, graph.add_node(page)))
+            .collect();
+        for (page, rules) in rulebook {
+            for after in &rules.comes_before {
+                graph.add_edge(nodes[page], nodes[after], ());
+            }
+        }
+        Self { graph, nodes }
+    }
+    // Only the rules where both pages are part of the update, with a node
+    // for each page in it so that repeated pages are kept
+    fn restrict(&self, pages: &[u32]) -> DiGraph<u32, ()> {
+        let mut sub = DiGraph::new();
+        let occurrences = pages.iter().map(|&p| sub.add_node(p)).collect::<Vec<_>>();
+        for (i, before) in pages.iter().enumerate() {
+            for (j, after) in pages.iter().enumerate() {
+                if let (Some(&b), Some(&a)) = (self.nodes.get(before), self.nodes.get(after)) {
+                    if self.graph.contains_edge(b, a) {
+                        sub.add_edge(occurrences[i], occurrences[j], ());
+                    }
+                }
+            }
+        }
+        sub
+    }
+    // Sort the restricted rules topologically; on failure, returns the
+    // pages that form a cycle
+    fn sort(sub: &DiGraph<u32, ()>) -> Result<Vec<NodeIndex>, Vec<u32>> {
+        petgraph::algo::toposort(sub, None).map_err(|cycle| {
+            let culprit = cycle.node_id();
+            petgraph::algo::tarjan_scc(sub)
+                .into_iter()
+                .find(|scc| scc.contains(&culprit))
+                .unwrap()
+                .into_iter()
+                .map(|n| sub[n])
+                .collect()
+        })
+    }
+    // Put the pages in an order that satisfies every rule
+    pub fn reorder(&self, pages: &[u32]) -> Result<Vec<u32>, Vec<u32>> {
+        let sub = self.restrict(pages);
+        Ok(Self::sort(&sub)?.into_iter().map(|n| sub[n]).collect())
+    }
+    // Whether the rules pin down exactly one valid ordering of the pages,
+    // which is only true if each page has a rule with the one after it (or
+    // is the same page again)
+    pub fn is_total_order(&self, pages: &[u32]) -> Result<bool, Vec<u32>> {
+        let sub = self.restrict(pages);
+        let order = Self::sort(&sub)?;
+        Ok(order
+            .windows(2)
+            .all(|w| sub[w[0]] == sub[w[1]] || sub.contains_edge(w[0], w[1])))
+    }
+}
+
 #[aoc(day5, part1, serial)]
 pub fn part1_serial((rulebook, manuals): &(HashMap<u32, Rules>, Vec<Vec<u32>>)) -> u32 {
     manuals
@@ -80,37 +191,32 @@ pub fn part1((rulebook, manuals): &(HashMap<u32, Rules>, Vec<Vec<u32>>)) -> u32
         .sum()
 }
 
+// The middle page of an update that was out of order, once it's fixed
+fn fixed_middle(graph: &RuleGraph, rulebook: &HashMap<u32, Rules>, pages: &[u32]) -> Option<u32> {
+    if is_valid(pages, rulebook).is_ok() {
+        return None;
+    }
+    let pages = graph
+        .reorder(pages)
+        .unwrap_or_else(|cycle| panic!("Rules for {pages:?} form a cycle: {cycle:?}"));
+    Some(pages[pages.len() / 2])
+}
+
 #[aoc(day5, part2, serial)]
 pub fn part2_serial((rulebook, manuals): &(HashMap<u32, Rules>, Vec<Vec<u32>>)) -> u32 {
+    let graph = RuleGraph::new(rulebook);
     manuals
         .iter()
-        .filter_map(|pages| {
-            if is_valid(pages, rulebook).is_ok() {
-                return None;
-            }
-            let mut pages = pages.to_owned();
-            while let Err((a, b)) = is_valid(&pages, rulebook) {
-                pages.swap(a, b);
-            }
-            Some(pages[pages.len() / 2])
-        })
+        .filter_map(|pages| fixed_middle(&graph, rulebook, pages))
         .sum()
 }
 
 #[aoc(day5, part2)]
 pub fn part2((rulebook, manuals): &(HashMap<u32, Rules>, Vec<Vec<u32>>)) -> u32 {
     use rayon::prelude::*;
+    let graph = RuleGraph::new(rulebook);
     manuals
         .par_iter()
-        .filter_map(|pages| {
-            if is_valid(pages, rulebook).is_ok() {
-                return None;
-            }
-            let mut pages = pages.to_owned();
-            while let Err((a, b)) = is_valid(&pages, rulebook) {
-                pages.swap(a, b);
-            }
-            Some(pages[pages.len() / 2])
-        })
+        .filter_map(|pages| fixed_middle(&graph, rulebook, pages))
         .sum()
 }
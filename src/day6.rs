@@ -1,44 +1,235 @@
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+};
 
 use aoc_runner_derive::{aoc, aoc_generator};
 
-use crate::util::Direction;
+use crate::util::{coords, Direction, DIRS};
+
+type Step = ((usize, usize), Direction);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TurnRule {
+    #[default]
+    Clockwise,
+    CounterClockwise,
+    Reverse,
+}
+
+impl TurnRule {
+    pub fn apply(&self, dir: Direction) -> Direction {
+        match self {
+            TurnRule::Clockwise => dir.cw(),
+            TurnRule::CounterClockwise => dir.ccw(),
+            TurnRule::Reverse => dir.opposite(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub extra: Option<(usize, usize)>,
+    // Each cell the guard stands in, and the way they face when leaving it
+    pub steps: Vec<Step>,
+    // The last cell before walking off the map, if the guard ever does
+    pub exit: Option<(usize, usize)>,
+    // Where in `steps` the guard starts repeating themselves, if they do
+    pub cycle_start: Option<usize>,
+}
+
+impl Route {
+    pub fn cycle(&self) -> &[Step] {
+        match self.cycle_start {
+            Some(start) => &self.steps[start..],
+            None => &[],
+        }
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Grid {
     rows: usize,
     cols: usize,
     guard: (usize, usize),
+    facing: Direction,
     obstacles: HashSet<(usize, usize)>,
+    // For each cell and direction, where the guard stops right before the
+    // next obstacle, or None if they walk off the map
+    jumps: Vec<[Option<(usize, usize)>; 4]>,
 }
 
 impl Grid {
-    pub fn makes_cycle(&self, extra: &(usize, usize)) -> bool {
-        let mut visited = HashSet::new();
+    fn build_jumps(
+        rows: usize,
+        cols: usize,
+        obstacles: &HashSet<(usize, usize)>,
+    ) -> Vec<[Option<(usize, usize)>; 4]> {
+        let mut jumps = vec![[None; 4]; rows * cols];
+        for dir in DIRS {
+            // Visit cells so the next one over in `dir` is always done first
+            let order: Box<dyn Iterator<Item = (usize, usize)>> = match dir {
+                Direction::Up | Direction::Left => Box::new(coords((rows, cols))),
+                Direction::Down | Direction::Right => {
+                    Box::new(coords((rows, cols)).collect::<Vec<_>>().into_iter().rev())
+                }
+            };
+            for posn in order {
+                jumps[posn.0 * cols + posn.1][dir as usize] =
+                    match dir.step_bounded(posn, (rows, cols)) {
+                        None => None,
+                        Some(next) if obstacles.contains(&next) => Some(posn),
+                        Some(next) => jumps[next.0 * cols + next.1][dir as usize],
+                    };
+            }
+        }
+        jumps
+    }
+    fn edge(&self, posn: (usize, usize), dir: Direction) -> (usize, usize) {
+        match dir {
+            Direction::Up => (0, posn.1),
+            Direction::Right => (posn.0, self.cols - 1),
+            Direction::Down => (self.rows - 1, posn.1),
+            Direction::Left => (posn.0, 0),
+        }
+    }
+    // Walk in a straight line until hitting an obstacle (including `extra`)
+    fn jump(
+        &self,
+        posn: (usize, usize),
+        dir: Direction,
+        extra: &(usize, usize),
+    ) -> Option<(usize, usize)> {
+        let stop = self.jumps[posn.0 * self.cols + posn.1][dir as usize];
+        let limit = stop.unwrap_or_else(|| self.edge(posn, dir));
+        // The extra obstacle only matters if it's between here and the limit
+        let (from, lane) = dir.ortho(posn);
+        let (to, _) = dir.ortho(limit);
+        let (ex, ex_lane) = dir.ortho(*extra);
+        if ex_lane == lane {
+            match dir {
+                Direction::Up | Direction::Left if ex < from && ex >= to => {
+                    return Some(dir.unortho(ex + 1, lane));
+                }
+                Direction::Down | Direction::Right if ex > from && ex <= to => {
+                    return Some(dir.unortho(ex - 1, lane));
+                }
+                _ => {}
+            }
+        }
+        stop
+    }
+    pub fn makes_cycle(&self, extra: &(usize, usize), turn: TurnRule) -> bool {
+        // Only the spots where the guard turns need to be remembered
+        let mut turns = HashSet::new();
         let mut posn = self.guard;
-        let mut dir = Direction::Up;
-        visited.insert((posn, dir));
+        let mut dir = self.facing;
+        while let Some(stop) = self.jump(posn, dir, extra) {
+            if !turns.insert((stop, dir)) {
+                // We've turned here, this exact way, before -- cyclic!
+                return true;
+            }
+            posn = stop;
+            dir = turn.apply(dir);
+        }
+        false
+    }
+    pub fn route(&self, extra: Option<(usize, usize)>, turn: TurnRule) -> Route {
+        let blocked = |p: &(usize, usize)| self.obstacles.contains(p) || extra.as_ref() == Some(p);
+        let mut seen = HashMap::new();
+        let mut steps = vec![];
+        let mut posn = self.guard;
+        let mut dir = self.facing;
         loop {
+            let mut turns = 0;
             let next = loop {
-                let Some(next) = dir.step_bounded(posn, (self.rows, self.cols)) else {
-                    break None;
-                };
-                if self.obstacles.contains(&next) || &next == extra {
-                    dir = dir.turn();
-                    continue;
+                match dir.step_bounded(posn, (self.rows, self.cols)) {
+                    Some(next) if blocked(&next) && turns < 4 => {
+                        dir = turn.apply(dir);
+                        turns += 1;
+                    }
+                    // Boxed in on all sides, so just "step" in place
+                    Some(next) if blocked(&next) => break Some(posn),
+                    next => break next,
                 }
-                break Some(next);
             };
+            if let Some(&start) = seen.get(&(posn, dir)) {
+                return Route {
+                    extra,
+                    steps,
+                    exit: None,
+                    cycle_start: Some(start),
+                };
+            }
+            seen.insert((posn, dir), steps.len());
+            steps.push((posn, dir));
             posn = match next {
                 Some(p) => p,
-                None => break,
+                None => {
+                    return Route {
+                        extra,
+                        steps,
+                        exit: Some(posn),
+                        cycle_start: None,
+                    }
+                }
             };
-            if !visited.insert((posn, dir)) {
-                // We've gone this exact direction before -- cyclic!
-                return true;
+        }
+    }
+    fn patrol(&self, turn: TurnRule) -> HashSet<(usize, usize)> {
+        self.route(None, turn)
+            .steps
+            .into_iter()
+            .map(|(p, _)| p)
+            .collect()
+    }
+    // Every obstruction that traps the guard, along with the loop they get stuck in
+    pub fn loops(&self, turn: TurnRule) -> Vec<((usize, usize), Vec<Step>)> {
+        use rayon::prelude::*;
+        let mut found = self
+            .patrol(turn)
+            .into_par_iter()
+            .filter(|posn| self.makes_cycle(posn, turn))
+            .map(|posn| (posn, self.route(Some(posn), turn).cycle().to_vec()))
+            .collect::<Vec<_>>();
+        found.sort();
+        found
+    }
+    pub fn render(&self, route: &Route) -> String {
+        // None marks a spot where the guard turned or crossed their own path
+        let mut marks: HashMap<(usize, usize), Option<Direction>> = HashMap::new();
+        let mut arriving = self.facing;
+        // Revisit the start of the loop (if any) so it gets closed off
+        for &(posn, dir) in route.steps.iter().chain(route.cycle().first()) {
+            let mark = if dir == arriving { Some(dir) } else { None };
+            marks
+                .entry(posn)
+                .and_modify(|m| {
+                    if *m != mark {
+                        *m = None;
+                    }
+                })
+                .or_insert(mark);
+            arriving = dir;
+        }
+        let mut out = String::new();
+        for r in 0..self.rows {
+            for c in 0..self.cols {
+                if self.obstacles.contains(&(r, c)) {
+                    out.push('#');
+                } else if route.extra == Some((r, c)) {
+                    out.push('O');
+                } else {
+                    match marks.get(&(r, c)) {
+                        Some(Some(dir)) => write!(out, "{dir}").unwrap(),
+                        Some(None) => out.push('+'),
+                        None => out.push('.'),
+                    }
+                }
             }
+            out.push('\n');
         }
-        false
+        out
     }
 }
 
@@ -46,13 +237,18 @@ impl Grid {
 pub fn gen(input: &str) -> Grid {
     let rows = input.lines().count();
     let cols = input.lines().next().unwrap().len();
-    let guard = input
+    let (guard, facing) = input
         .lines()
         .enumerate()
-        .flat_map(|(r, l)| l.chars().position(|ch| ch == '^').map(move |c| (r, c)))
+        .flat_map(|(r, l)| {
+            l.chars()
+                .enumerate()
+                .find(|(_, ch)| "^>v<".contains(*ch))
+                .map(move |(c, ch)| ((r, c), Direction::from(ch)))
+        })
         .next()
         .unwrap();
-    let obstacles = input
+    let obstacles: HashSet<_> = input
         .lines()
         .enumerate()
         .flat_map(|(r, l)| {
@@ -61,67 +257,27 @@ pub fn gen(input: &str) -> Grid {
                 .filter_map(move |(c, ch)| if ch == '#' { Some((r, c)) } else { None })
         })
         .collect();
+    let jumps = Grid::build_jumps(rows, cols, &obstacles);
     Grid {
         rows,
         cols,
         guard,
+        facing,
         obstacles,
+        jumps,
     }
 }
 
 #[aoc(day6, part1)]
 pub fn part1(grid: &Grid) -> usize {
-    let mut visited = HashSet::new();
-    let mut posn = grid.guard;
-    let mut dir = Direction::Up;
-    visited.insert(posn);
-    loop {
-        let next = loop {
-            let Some(next) = dir.step_bounded(posn, (grid.rows, grid.cols)) else {
-                break None;
-            };
-            if grid.obstacles.contains(&next) {
-                dir = dir.turn();
-                continue;
-            }
-            break Some(next);
-        };
-        posn = match next {
-            Some(p) => p,
-            None => break,
-        };
-        visited.insert(posn);
-    }
-    visited.len()
+    grid.patrol(TurnRule::Clockwise).len()
 }
 
 #[aoc(day6, part2)]
 pub fn part2(grid: &Grid) -> usize {
     use rayon::prelude::*;
-    let mut visited = HashSet::new();
-    let mut posn = grid.guard;
-    let mut dir = Direction::Up;
-    visited.insert(posn);
-    loop {
-        let next = loop {
-            let Some(next) = dir.step_bounded(posn, (grid.rows, grid.cols)) else {
-                break None;
-            };
-            if grid.obstacles.contains(&next) {
-                dir = dir.turn();
-                continue;
-            }
-            break Some(next);
-        };
-        posn = match next {
-            Some(p) => p,
-            None => break,
-        };
-        visited.insert(posn);
-    }
-
-    visited
+    grid.patrol(TurnRule::Clockwise)
         .par_drain()
-        .filter(|posn| grid.makes_cycle(posn))
+        .filter(|posn| grid.makes_cycle(posn, TurnRule::Clockwise))
         .count()
 }
@@ -1,84 +1,314 @@
-use std::hint::unreachable_unchecked;
+use std::{
+    fmt::{Debug, Display},
+    str::FromStr,
+};
 
 use aoc_runner_derive::{aoc, aoc_generator};
+use num_bigint::BigUint;
+use num_traits::{CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, Num};
+
+// Anything an equation can be written in, e.g. `u64`, `u128`, or `BigUint`
+pub trait Number:
+    Num
+    + CheckedAdd
+    + CheckedSub
+    + CheckedMul
+    + CheckedDiv
+    + From<u8>
+    + Clone
+    + Ord
+    + Debug
+    + Display
+    + FromStr
+    + Send
+    + Sync
+    + 'static
+{
+}
+
+impl<T> Number for T where
+    T: Num
+        + CheckedAdd
+        + CheckedSub
+        + CheckedMul
+        + CheckedDiv
+        + From<u8>
+        + Clone
+        + Ord
+        + Debug
+        + Display
+        + FromStr
+        + Send
+        + Sync
+        + 'static
+{
+}
 
 #[derive(Debug)]
-pub struct Equation {
-    pub answer: u64,
-    pub numbers: Vec<u64>,
+pub struct Equation<T = u64> {
+    pub answer: T,
+    pub numbers: Vec<T>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inverse<T> {
+    Impossible,
+    Exactly(T),
+    // Any left operand in this (inclusive) range works, e.g. for truncating division
+    Between(T, T),
+    // Like `Between`, but with no upper bound because it's past what `T` can hold
+    AtLeast(T),
+    // Every left operand works, e.g. multiplying by zero
+    Any,
 }
 
-#[derive(Debug, Clone, Copy)]
-pub enum Operation {
-    Add,
-    Multiply,
-    Concatenate,
+pub trait Operator<T>: Debug + Sync {
+    fn symbol(&self) -> &'static str;
+    // None if the result doesn't fit (or isn't defined)
+    fn apply(&self, left: &T, right: &T) -> Option<T>;
+    // Which left operand, combined with `right`, gives `result`
+    fn inverse(&self, result: &T, right: &T) -> Inverse<T>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Add;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Multiply;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Concatenate;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Subtract;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Divide;
+
+pub fn basic<T: Number>() -> [&'static dyn Operator<T>; 2] {
+    [&Add, &Multiply]
+}
+
+pub fn extended<T: Number>() -> [&'static dyn Operator<T>; 3] {
+    [&Add, &Multiply, &Concatenate]
+}
+
+impl<T: Number> Operator<T> for Add {
+    fn symbol(&self) -> &'static str {
+        "+"
+    }
+    fn apply(&self, left: &T, right: &T) -> Option<T> {
+        left.checked_add(right)
+    }
+    fn inverse(&self, result: &T, right: &T) -> Inverse<T> {
+        result
+            .checked_sub(right)
+            .map_or(Inverse::Impossible, Inverse::Exactly)
+    }
+}
+
+impl<T: Number> Operator<T> for Multiply {
+    fn symbol(&self) -> &'static str {
+        "*"
+    }
+    fn apply(&self, left: &T, right: &T) -> Option<T> {
+        left.checked_mul(right)
+    }
+    fn inverse(&self, result: &T, right: &T) -> Inverse<T> {
+        match (result.is_zero(), right.is_zero()) {
+            (true, true) => Inverse::Any,
+            (false, true) => Inverse::Impossible,
+            _ if (result.clone() % right.clone()).is_zero() => {
+                Inverse::Exactly(result.clone() / right.clone())
+            }
+            _ => Inverse::Impossible,
+        }
+    }
 }
 
-impl Equation {
+impl Concatenate {
+    // What the left operand gets multiplied by to make room for `right`
+    fn shift<T: Number>(right: &T) -> Option<T> {
+        let ten = T::from(10);
+        let mut shift = ten.clone();
+        while &shift <= right {
+            shift = shift.checked_mul(&ten)?;
+        }
+        Some(shift)
+    }
+}
+
+impl<T: Number> Operator<T> for Concatenate {
+    fn symbol(&self) -> &'static str {
+        "||"
+    }
+    fn apply(&self, left: &T, right: &T) -> Option<T> {
+        match Self::shift(right) {
+            Some(shift) => left.checked_mul(&shift)?.checked_add(right),
+            None if left.is_zero() => Some(right.clone()),
+            None => None,
+        }
+    }
+    fn inverse(&self, result: &T, right: &T) -> Inverse<T> {
+        match Self::shift(right) {
+            Some(shift) if &(result.clone() % shift.clone()) == right => {
+                Inverse::Exactly(result.clone() / shift)
+            }
+            None if result == right => Inverse::Exactly(T::zero()),
+            _ => Inverse::Impossible,
+        }
+    }
+}
+
+impl<T: Number> Operator<T> for Subtract {
+    fn symbol(&self) -> &'static str {
+        "-"
+    }
+    fn apply(&self, left: &T, right: &T) -> Option<T> {
+        left.checked_sub(right)
+    }
+    fn inverse(&self, result: &T, right: &T) -> Inverse<T> {
+        result
+            .checked_add(right)
+            .map_or(Inverse::Impossible, Inverse::Exactly)
+    }
+}
+
+impl<T: Number> Operator<T> for Divide {
+    fn symbol(&self) -> &'static str {
+        "/"
+    }
+    fn apply(&self, left: &T, right: &T) -> Option<T> {
+        left.checked_div(right)
+    }
+    fn inverse(&self, result: &T, right: &T) -> Inverse<T> {
+        if right.is_zero() {
+            return Inverse::Impossible;
+        }
+        // Division truncates, so anything up to the next multiple works too
+        let Some(low) = result.checked_mul(right) else {
+            return Inverse::Impossible;
+        };
+        match low.checked_add(&(right.clone() - T::one())) {
+            Some(high) => Inverse::Between(low, high),
+            None => Inverse::AtLeast(low),
+        }
+    }
+}
+
+// The operators that solve an equation, e.g. `81 + 40 * 27`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Witness<T = u64> {
+    pub numbers: Vec<T>,
+    pub symbols: Vec<&'static str>,
+}
+
+impl<T: Display> Display for Witness<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.numbers[0])?;
+        for (sym, num) in self.symbols.iter().zip(&self.numbers[1..]) {
+            write!(f, " {sym} {num}")?;
+        }
+        Ok(())
+    }
+}
+
+// Evaluate left to right, looking for any choice of operators whose result is
+// accepted. Chosen operators are pushed onto `chosen`.
+fn search_forward<T: Number>(
+    acc: &T,
+    rest: &[T],
+    ops: &[&dyn Operator<T>],
+    chosen: &mut Vec<usize>,
+    accept: &impl Fn(&T) -> bool,
+) -> bool {
+    let Some((next, rest)) = rest.split_first() else {
+        return accept(acc);
+    };
+    for (o, op) in ops.iter().enumerate() {
+        let Some(acc) = op.apply(acc, next) else {
+            continue;
+        };
+        chosen.push(o);
+        if search_forward(&acc, rest, ops, chosen, accept) {
+            return true;
+        }
+        chosen.pop();
+    }
+    false
+}
+
+// Work right to left, undoing each operator; most operators only have one
+// possible left operand (if any), which prunes the search quickly
+fn search_backward<T: Number>(
+    target: &T,
+    numbers: &[T],
+    ops: &[&dyn Operator<T>],
+    chosen: &mut Vec<usize>,
+) -> bool {
+    let (last, rest) = numbers.split_last().unwrap();
+    if rest.is_empty() {
+        return last == target;
+    }
+    for (o, op) in ops.iter().enumerate() {
+        let found = match op.inverse(target, last) {
+            Inverse::Impossible => false,
+            Inverse::Exactly(left) => search_backward(&left, rest, ops, chosen),
+            Inverse::Between(low, high) => {
+                search_forward(&rest[0], &rest[1..], ops, chosen, &|v| {
+                    &low <= v && v <= &high
+                })
+            }
+            Inverse::AtLeast(low) => {
+                search_forward(&rest[0], &rest[1..], ops, chosen, &|v| &low <= v)
+            }
+            Inverse::Any => search_forward(&rest[0], &rest[1..], ops, chosen, &|_| true),
+        };
+        if found {
+            chosen.push(o);
+            return true;
+        }
+    }
+    false
+}
+
+impl<T: Number> Equation<T> {
     pub fn parse(s: &str) -> Self {
         let (answer, rest) = s.split_once(": ").unwrap();
-        let answer = answer.parse().unwrap();
+        let answer = answer.parse().ok().unwrap();
         let numbers = rest
             .split_whitespace()
-            .map(|n| n.parse().unwrap())
+            .map(|n| n.parse().ok().unwrap())
             .collect();
         Self { answer, numbers }
     }
-    pub fn can_solve(&self) -> bool {
-        let num_ops = self.numbers.len() - 1;
-        for ops in 0u64..2u64.pow(num_ops as u32) {
-            let op_iter = (0..num_ops)
-                .map(|m| {
-                    if (ops & (0x1 << m)) == 0 {
-                        Operation::Multiply
-                    } else {
-                        Operation::Add
-                    }
-                })
-                .zip(&self.numbers[1..]);
-            let mut out = self.numbers[0];
-            for (op, num) in op_iter {
-                out = match op {
-                    Operation::Add => out.checked_add(*num).unwrap(),
-                    Operation::Multiply => out.checked_mul(*num).unwrap(),
-                    _ => unsafe { unreachable_unchecked() },
-                };
-            }
-            if out == self.answer {
-                return true;
-            }
+    pub fn solve(&self, ops: &[&dyn Operator<T>]) -> Option<Witness<T>> {
+        let mut chosen = vec![];
+        if !search_backward(&self.answer, &self.numbers, ops, &mut chosen) {
+            return None;
         }
-        false
+        Some(Witness {
+            numbers: self.numbers.clone(),
+            symbols: chosen.into_iter().map(|o| ops[o].symbol()).collect(),
+        })
+    }
+    pub fn can_solve(&self) -> bool {
+        self.solve(&basic()).is_some()
     }
     pub fn can_solve_extended(&self) -> bool {
-        let num_ops = self.numbers.len() - 1;
-        let mut ops = vec![Operation::Add; num_ops];
-        for mut op in 0u64..3u64.pow(num_ops as u32) {
-            for i in ops.iter_mut() {
-                *i = match op % 3 {
-                    0 => Operation::Add,
-                    1 => Operation::Multiply,
-                    2 => Operation::Concatenate,
-                    _ => unreachable!(),
-                };
-                op /= 3;
-            }
-            let mut out = self.numbers[0];
-            for (op, num) in ops.iter().zip(&self.numbers[1..]) {
-                out = match op {
-                    Operation::Add => out.checked_add(*num).unwrap(),
-                    Operation::Multiply => out.checked_mul(*num).unwrap(),
-                    Operation::Concatenate => format!("{out}{num}").parse().unwrap(),
-                };
-            }
-            if out == self.answer {
-                return true;
-            }
-        }
-        false
+        self.solve(&extended()).is_some()
     }
 }
+
+// Total of every equation that some choice of `ops` can solve, or None if
+// that's too big for `T`
+pub fn calibrate<T: Number>(eqns: &[Equation<T>], ops: &[&dyn Operator<T>]) -> Option<T> {
+    use rayon::prelude::*;
+    eqns.par_iter()
+        .filter_map(|e| e.solve(ops).map(|_| e.answer.clone()))
+        .map(Some)
+        .reduce(
+            || Some(T::zero()),
+            |a, b| a.zip(b).and_then(|(a, b)| a.checked_add(&b)),
+        )
+}
+
 #[aoc_generator(day7)]
 pub fn gen(input: &str) -> Vec<Equation> {
     input.lines().map(Equation::parse).collect()
@@ -86,16 +316,21 @@ pub fn gen(input: &str) -> Vec<Equation> {
 
 #[aoc(day7, part1)]
 pub fn part1(eqns: &[Equation]) -> u64 {
-    use rayon::prelude::*;
-    eqns.par_iter()
-        .filter_map(|e| e.can_solve().then_some(e.answer))
-        .sum()
+    calibrate(eqns, &basic()).expect("Total overflowed")
 }
 
 #[aoc(day7, part2)]
 pub fn part2(eqns: &[Equation]) -> u64 {
-    use rayon::prelude::*;
-    eqns.par_iter()
-        .filter_map(|e| (e.can_solve() || e.can_solve_extended()).then_some(e.answer))
-        .sum()
+    calibrate(eqns, &extended()).expect("Total overflowed")
+}
+
+// For answers too big for a u64; a BigUint total can't overflow
+#[aoc_generator(day7, part2, big)]
+pub fn gen_big(input: &str) -> Vec<Equation<BigUint>> {
+    input.lines().map(Equation::parse).collect()
+}
+
+#[aoc(day7, part2, big)]
+pub fn part2_big(eqns: &[Equation<BigUint>]) -> BigUint {
+    calibrate(eqns, &extended()).unwrap()
 }
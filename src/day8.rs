@@ -1,7 +1,4 @@
-use std::{
-    collections::{HashMap, HashSet},
-    ops::Range,
-};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use aoc_runner_derive::{aoc, aoc_generator};
 
@@ -33,107 +30,159 @@ pub fn gen(input: &str) -> City {
 
 type Point = (usize, usize);
 
-fn antinode_posns(a: Point, b: Point, grid: (usize, usize)) -> (Option<Point>, Option<Point>) {
-    let row_rng = 0..(grid.0 as isize);
-    let col_rng = 0..(grid.1 as isize);
-    let (ar, ac) = (a.0 as isize, a.1 as isize);
-    let (br, bc) = (b.0 as isize, b.1 as isize);
-    // Get the rise and run
-    let rise = ar - br;
-    let run = ac - bc;
-    let posn1 = (ar + rise, ac + run);
-    let posn2 = (br - rise, bc - run);
-    let posn1 = if row_rng.contains(&posn1.0) && col_rng.contains(&posn1.1) {
-        Some((posn1.0 as usize, posn1.1 as usize))
-    } else {
-        None
-    };
-    let posn2 = if row_rng.contains(&posn2.0) && col_rng.contains(&posn2.1) {
-        Some((posn2.0 as usize, posn2.1 as usize))
-    } else {
-        None
-    };
-    (posn1, posn2)
+// Which multiples of the step between two antennas, counted out past each
+// antenna, make an antinode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Harmonics {
+    Set(Vec<usize>),
+    All,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resonance {
+    pub harmonics: Harmonics,
+    // Step by the smallest grid offset along the line instead of the full
+    // offset between the antennas
+    pub reduce: bool,
 }
 
-struct PointIter {
-    rise: isize,
-    run: isize,
-    bounds: (Range<isize>, Range<isize>),
-    curr: (isize, isize),
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Report {
+    // How many distinct spots each frequency makes an antinode at
+    pub by_frequency: BTreeMap<char, usize>,
+    // Spots where more than one frequency makes an antinode
+    pub overlaps: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Antinode {
+    pub posn: Point,
+    pub frequency: char,
+    pub pair: (Point, Point),
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
 }
 
-impl Iterator for PointIter {
-    type Item = (usize, usize);
-    fn next(&mut self) -> Option<Self::Item> {
-        let (r, c) = self.curr;
-        let out = if self.bounds.0.contains(&r) && self.bounds.1.contains(&c) {
+impl City {
+    fn at(&self, a: Point, step: (isize, isize), k: isize) -> Option<Point> {
+        let r = a.0 as isize + step.0 * k;
+        let c = a.1 as isize + step.1 * k;
+        if (0..self.rows as isize).contains(&r) && (0..self.cols as isize).contains(&c) {
             Some((r as usize, c as usize))
         } else {
             None
+        }
+    }
+    // Every point on the line through `a` and `b` is `a + k * step`; `b` is
+    // at `k = -g`
+    fn resonate(&self, a: Point, b: Point, resonance: &Resonance) -> Vec<Point> {
+        let (rise, run) = (a.0 as isize - b.0 as isize, a.1 as isize - b.1 as isize);
+        let g = if resonance.reduce {
+            gcd(rise.unsigned_abs(), run.unsigned_abs()) as isize
+        } else {
+            1
         };
-        self.curr = (self.curr.0 + self.rise, self.curr.1 + self.run);
-        out
+        let step = (rise / g, run / g);
+        match &resonance.harmonics {
+            Harmonics::Set(ns) => ns
+                .iter()
+                .flat_map(|&n| [n as isize, -g - n as isize])
+                .filter_map(|k| self.at(a, step, k))
+                .collect(),
+            Harmonics::All => {
+                let forward = (0..).map_while(|k| self.at(a, step, k));
+                let backward = (1..).map_while(|k| self.at(a, step, -k));
+                forward.chain(backward).collect()
+            }
+        }
     }
-}
-
-fn antinodes(a: Point, b: Point, grid: (usize, usize)) -> impl Iterator<Item = (usize, usize)> {
-    let row_rng = 0..(grid.0 as isize);
-    let col_rng = 0..(grid.1 as isize);
-    let (ar, ac) = (a.0 as isize, a.1 as isize);
-    let (br, bc) = (b.0 as isize, b.1 as isize);
-    // Get the rise and run
-    let rise = ar - br;
-    let run = ac - bc;
-    PointIter {
-        bounds: (row_rng.clone(), col_rng.clone()),
-        curr: (ar, ac),
-        rise,
-        run,
+    pub fn antinodes(&self, resonance: &Resonance) -> Vec<Antinode> {
+        let mut found = vec![];
+        for (&frequency, posns) in &self.by_antenna {
+            let mut posns = posns.iter().copied().collect::<Vec<_>>();
+            posns.sort();
+            for (i, &a) in posns.iter().enumerate() {
+                for &b in &posns[i + 1..] {
+                    found.extend(
+                        self.resonate(a, b, resonance)
+                            .into_iter()
+                            .map(|posn| Antinode {
+                                posn,
+                                frequency,
+                                pair: (a, b),
+                            }),
+                    );
+                }
+            }
+        }
+        found
     }
-    .chain(PointIter {
-        bounds: (row_rng.clone(), col_rng.clone()),
-        curr: (br, bc),
-        rise: -rise,
-        run: -run,
-    })
-}
-
-#[aoc(day8, part1)]
-pub fn part1(input: &City) -> usize {
-    let mut antinodes = HashSet::new();
-    let grid = (input.rows, input.cols);
-    for posns in input.by_antenna.values() {
-        for me in posns {
-            let mut posns = posns.to_owned();
-            posns.remove(me);
+    pub fn report(&self, resonance: &Resonance) -> Report {
+        let mut by_posn: HashMap<Point, HashSet<char>> = HashMap::new();
+        for a in self.antinodes(resonance) {
+            by_posn.entry(a.posn).or_default().insert(a.frequency);
+        }
+        let mut report = Report {
+            total: by_posn.len(),
+            ..Default::default()
+        };
+        for freqs in by_posn.values() {
+            if freqs.len() > 1 {
+                report.overlaps += 1;
+            }
+            for f in freqs {
+                *report.by_frequency.entry(*f).or_default() += 1;
+            }
+        }
+        report
+    }
+    pub fn render(&self, resonance: &Resonance) -> String {
+        let antinodes = self
+            .antinodes(resonance)
+            .into_iter()
+            .map(|a| a.posn)
+            .collect::<HashSet<_>>();
+        let mut grid = vec![vec!['.'; self.cols]; self.rows];
+        for p in antinodes {
+            grid[p.0][p.1] = '#';
+        }
+        // Antennas win out, so the original map is still readable
+        for (&freq, posns) in &self.by_antenna {
             for p in posns {
-                let (p1, p2) = antinode_posns(*me, p, grid);
-                if let Some(p1) = p1 {
-                    antinodes.insert(p1);
-                }
-                if let Some(p2) = p2 {
-                    antinodes.insert(p2);
-                }
+                grid[p.0][p.1] = freq;
             }
         }
+        grid.into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .chain(std::iter::once('\n'))
+                    .collect::<String>()
+            })
+            .collect()
     }
+}
 
-    antinodes.len()
+#[aoc(day8, part1)]
+pub fn part1(input: &City) -> usize {
+    let resonance = Resonance {
+        harmonics: Harmonics::Set(vec![1]),
+        reduce: false,
+    };
+    input.report(&resonance).total
 }
 
 #[aoc(day8, part2)]
 pub fn part2(input: &City) -> usize {
-    let mut places = HashSet::new();
-    let grid = (input.rows, input.cols);
-    for posns in input.by_antenna.values() {
-        for me in posns {
-            let mut posns = posns.to_owned();
-            posns.remove(me);
-            for p in posns {
-                places.extend(antinodes(*me, p, grid));
-            }
-        }
-    }
-    places.len()
+    let resonance = Resonance {
+        harmonics: Harmonics::All,
+        reduce: false,
+    };
+    input.report(&resonance).total
 }
@@ -1,3 +1,9 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap},
+    fmt::Display,
+};
+
 use aoc_runner_derive::{aoc, aoc_generator};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -53,73 +59,325 @@ pub fn gen_files(input: &str) -> Vec<File> {
         .collect()
 }
 
-#[aoc(day9, part1)]
-pub fn part1(blocks: &[Block]) -> usize {
+// Move blocks from the end into the first free slot, working inwards from
+// both ends
+pub fn compact_blocks(blocks: &[Block]) -> Vec<Block> {
     let mut compacted = blocks.to_vec();
-    for b in (0..blocks.len()).rev() {
-        let Some(idx) = compacted.iter().position(|cblock| *cblock == Block::Free) else {
-            break;
-        };
-        if idx >= b {
+    if compacted.is_empty() {
+        return compacted;
+    }
+    let (mut free, mut used) = (0, compacted.len() - 1);
+    loop {
+        while free < compacted.len() && compacted[free] != Block::Free {
+            free += 1;
+        }
+        while used > 0 && compacted[used] == Block::Free {
+            used -= 1;
+        }
+        if free >= used {
             break;
         }
-        compacted.swap(b, idx);
+        compacted.swap(free, used);
     }
     compacted
-        .into_iter()
-        .enumerate()
-        .map(|(b, block)| match block {
-            Block::Free => 0,
-            Block::Used { id } => b * id,
-        })
-        .sum()
 }
 
-#[aoc(day9, part2)]
-pub fn part2(files: &[File]) -> usize {
-    let mut compacted = files.to_vec();
-    for file in files.iter().rev() {
-        if file.block == Block::Free {
-            continue;
+// Move each file (highest id first) into the leftmost free span that fits it.
+// Free spans are kept in a min-heap (by position) for each size, so finding
+// the leftmost fit only means peeking at a handful of heaps. Returns where
+// each file ends up starting.
+pub fn compact_files(files: &[File]) -> Vec<(usize, File)> {
+    let largest = files.iter().map(|f| f.num_blocks).max().unwrap_or(0);
+    let mut free_spans = vec![BinaryHeap::new(); largest + 1];
+    let mut placed = vec![];
+    let mut start = 0;
+    for file in files {
+        match file.block {
+            Block::Free if file.num_blocks > 0 => free_spans[file.num_blocks].push(Reverse(start)),
+            Block::Free => {}
+            Block::Used { .. } => placed.push((start, *file)),
         }
-        let Some(idx) = compacted
-            .iter()
-            .position(|cfile| cfile.num_blocks >= file.num_blocks && cfile.block == Block::Free)
+        start += file.num_blocks;
+    }
+    for (start, file) in placed.iter_mut().rev() {
+        let Some((span, size)) = (file.num_blocks..free_spans.len())
+            .filter_map(|size| free_spans[size].peek().map(|&Reverse(s)| (s, size)))
+            .min()
         else {
-            // Just because we couldn't be placed doesn't mean others can't
             continue;
         };
-        let fc = compacted.iter().position(|f| f == file).unwrap();
-        // Check that we're moving in the right direction
-        if idx >= fc {
+        // Only ever move files to the left. Whatever space the file leaves
+        // behind is to the right of every file still left to move, so it's
+        // never worth tracking
+        if span >= *start {
             continue;
         }
-        // Replace the file with free space. Keep in mind it might have moved
-        // so we need to find it
-        compacted[fc] = File {
-            num_blocks: file.num_blocks,
-            block: Block::Free,
-        };
-        // Decrease the free size
-        compacted[idx].num_blocks -= file.num_blocks;
-        // Insert a new file before (or replace if possible)
-        if compacted[idx].num_blocks == 0 {
-            compacted[idx] = *file;
-        } else {
-            compacted.insert(idx, *file);
+        free_spans[size].pop();
+        if size > file.num_blocks {
+            free_spans[size - file.num_blocks].push(Reverse(span + file.num_blocks));
         }
+        *start = span;
     }
+    placed
+}
 
-    compacted
-        .into_iter()
-        .flat_map(|file| {
-            let n = match file.block {
-                Block::Free => 0,
-                Block::Used { id } => id,
-            };
-            std::iter::repeat_n(n, file.num_blocks)
+pub fn file_checksum(placed: &[(usize, File)]) -> usize {
+    placed
+        .iter()
+        .map(|(start, file)| match file.block {
+            Block::Free => 0,
+            // id * (start + (start + 1) + ... + (start + n - 1))
+            Block::Used { id } => {
+                let n = file.num_blocks;
+                id * (n * start + n * n.saturating_sub(1) / 2)
+            }
         })
-        .enumerate()
-        .map(|(i, n)| i * n)
         .sum()
 }
+
+// Where every (piece of a) file sits on a disk `len` blocks long
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    len: usize,
+    placed: Vec<(usize, File)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Fragmentation {
+    pub free_spans: usize,
+    pub largest_free_span: usize,
+}
+
+impl Layout {
+    fn new(len: usize, mut placed: Vec<(usize, File)>) -> Self {
+        // Empty files are kept around, since they still split up free spans
+        placed.retain(|(_, f)| f.block != Block::Free);
+        placed.sort_by_key(|(start, _)| *start);
+        Self { len, placed }
+    }
+    pub fn from_files(files: &[File]) -> Self {
+        let mut placed = vec![];
+        let mut start = 0;
+        for file in files {
+            placed.push((start, *file));
+            start += file.num_blocks;
+        }
+        Self::new(start, placed)
+    }
+    pub fn from_blocks(blocks: &[Block]) -> Self {
+        let mut placed: Vec<(usize, File)> = vec![];
+        for (b, block) in blocks.iter().enumerate() {
+            match placed.last_mut() {
+                Some((start, file)) if file.block == *block && *start + file.num_blocks == b => {
+                    file.num_blocks += 1;
+                }
+                _ => placed.push((
+                    b,
+                    File {
+                        num_blocks: 1,
+                        block: *block,
+                    },
+                )),
+            }
+        }
+        Self::new(blocks.len(), placed)
+    }
+    pub fn to_blocks(&self) -> Vec<Block> {
+        let mut blocks = vec![Block::Free; self.len];
+        for (start, file) in &self.placed {
+            blocks[*start..*start + file.num_blocks].fill(file.block);
+        }
+        blocks
+    }
+    pub fn to_files(&self) -> Vec<File> {
+        let mut files = self
+            .free_spans()
+            .into_iter()
+            .map(|(start, num_blocks)| {
+                let block = Block::Free;
+                (start, File { num_blocks, block })
+            })
+            .chain(self.placed.iter().copied())
+            .collect::<Vec<_>>();
+        files.sort_by_key(|(start, _)| *start);
+        files.into_iter().map(|(_, f)| f).collect()
+    }
+    // Every run of free blocks, as (start, length)
+    pub fn free_spans(&self) -> Vec<(usize, usize)> {
+        let mut spans = vec![];
+        let mut end = 0;
+        for (start, file) in &self.placed {
+            if *start > end {
+                spans.push((end, start - end));
+            }
+            end = end.max(start + file.num_blocks);
+        }
+        if self.len > end {
+            spans.push((end, self.len - end));
+        }
+        spans
+    }
+    pub fn fragmentation(&self) -> Fragmentation {
+        let spans = self.free_spans();
+        Fragmentation {
+            free_spans: spans.len(),
+            largest_free_span: spans.iter().map(|(_, n)| *n).max().unwrap_or(0),
+        }
+    }
+    pub fn checksum(&self) -> usize {
+        file_checksum(&self.placed)
+    }
+}
+
+impl Display for Layout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for block in self.to_blocks() {
+            let ch = match block {
+                Block::Free => '.',
+                Block::Used { id } => char::from_digit(id as u32, 36).unwrap_or('?'),
+            };
+            write!(f, "{ch}")?;
+        }
+        Ok(())
+    }
+}
+
+pub trait CompactionStrategy {
+    fn compact(&self, layout: &Layout) -> Layout;
+}
+
+// Move single blocks from the end into the first free block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BlockSwap;
+// Move whole files to the leftmost free span that fits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LeftmostFit;
+// Move whole files to the smallest free span that fits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BestFit;
+// Move whole files to the largest free span that fits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WorstFit;
+// Move whole files to the leftmost free span that fits, but only if that
+// leaves fewer free spans than before
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Defragment;
+
+impl CompactionStrategy for BlockSwap {
+    fn compact(&self, layout: &Layout) -> Layout {
+        Layout::from_blocks(&compact_blocks(&layout.to_blocks()))
+    }
+}
+
+impl CompactionStrategy for LeftmostFit {
+    fn compact(&self, layout: &Layout) -> Layout {
+        Layout::new(layout.len, compact_files(&layout.to_files()))
+    }
+}
+
+// Move each file (highest id first) into whichever free span to its left
+// `pick` chooses, out of (start, length) candidates that fit it. Unlike
+// `compact_files`, this keeps track of the space files leave behind.
+fn relocate(
+    layout: &Layout,
+    pick: impl Fn(&[(usize, usize)]) -> Option<(usize, usize)>,
+    defragment_only: bool,
+) -> Layout {
+    let mut free: BTreeMap<usize, usize> = layout.free_spans().into_iter().collect();
+    let mut placed = layout.placed.clone();
+    placed.sort_by_key(|(_, f)| match f.block {
+        Block::Free => 0,
+        Block::Used { id } => id,
+    });
+    for (start, file) in placed.iter_mut().rev() {
+        let n = file.num_blocks;
+        if n == 0 {
+            continue;
+        }
+        let candidates = free
+            .range(..*start)
+            .filter(|(_, &len)| len >= n)
+            .map(|(&s, &len)| (s, len))
+            .collect::<Vec<_>>();
+        let Some((span, len)) = pick(&candidates) else {
+            continue;
+        };
+        free.remove(&span);
+        if len > n {
+            free.insert(span + n, len - n);
+        }
+        // See what the space we'd leave behind would join up with
+        let before = free
+            .range(..*start)
+            .next_back()
+            .filter(|(&s, &len)| s + len == *start)
+            .map(|(&s, &len)| (s, len));
+        let after = free.get(&(*start + n)).copied();
+        if defragment_only {
+            let added = 1 + usize::from(len > n);
+            let removed = 1 + usize::from(before.is_some()) + usize::from(after.is_some());
+            if added >= removed {
+                // Not worth it, so put the span back the way it was
+                if len > n {
+                    free.remove(&(span + n));
+                }
+                free.insert(span, len);
+                continue;
+            }
+        }
+        let (mut vacated, mut vacated_len) = (*start, n);
+        if let Some((s, len)) = before {
+            free.remove(&s);
+            vacated = s;
+            vacated_len += len;
+        }
+        if let Some(len) = after {
+            free.remove(&(*start + n));
+            vacated_len += len;
+        }
+        free.insert(vacated, vacated_len);
+        *start = span;
+    }
+    Layout::new(layout.len, placed)
+}
+
+impl CompactionStrategy for BestFit {
+    fn compact(&self, layout: &Layout) -> Layout {
+        relocate(
+            layout,
+            |spans| spans.iter().copied().min_by_key(|&(s, len)| (len, s)),
+            false,
+        )
+    }
+}
+
+impl CompactionStrategy for WorstFit {
+    fn compact(&self, layout: &Layout) -> Layout {
+        relocate(
+            layout,
+            |spans| {
+                spans
+                    .iter()
+                    .copied()
+                    .min_by_key(|&(s, len)| (Reverse(len), s))
+            },
+            false,
+        )
+    }
+}
+
+impl CompactionStrategy for Defragment {
+    fn compact(&self, layout: &Layout) -> Layout {
+        relocate(layout, |spans| spans.first().copied(), true)
+    }
+}
+
+#[aoc(day9, part1)]
+pub fn part1(blocks: &[Block]) -> usize {
+    BlockSwap.compact(&Layout::from_blocks(blocks)).checksum()
+}
+
+#[aoc(day9, part2)]
+pub fn part2(files: &[File]) -> usize {
+    LeftmostFit.compact(&Layout::from_files(files)).checksum()
+}
@@ -115,6 +115,58 @@ impl Display for Direction {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Heading {
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest,
+}
+
+pub const HEADINGS: [Heading; 8] = [
+    Heading::North,
+    Heading::NorthEast,
+    Heading::East,
+    Heading::SouthEast,
+    Heading::South,
+    Heading::SouthWest,
+    Heading::West,
+    Heading::NorthWest,
+];
+
+impl Heading {
+    pub fn delta(&self) -> (isize, isize) {
+        match self {
+            Heading::North => (-1, 0),
+            Heading::NorthEast => (-1, 1),
+            Heading::East => (0, 1),
+            Heading::SouthEast => (1, 1),
+            Heading::South => (1, 0),
+            Heading::SouthWest => (1, -1),
+            Heading::West => (0, -1),
+            Heading::NorthWest => (-1, -1),
+        }
+    }
+    pub fn step_bounded(
+        &self,
+        posn: (usize, usize),
+        bounds: (usize, usize),
+    ) -> Option<(usize, usize)> {
+        let (dr, dc) = self.delta();
+        let r = posn.0.checked_add_signed(dr).filter(|&r| r < bounds.0)?;
+        let c = posn.1.checked_add_signed(dc).filter(|&c| c < bounds.1)?;
+        Some((r, c))
+    }
+    pub fn is_cardinal(&self) -> bool {
+        let (dr, dc) = self.delta();
+        dr == 0 || dc == 0
+    }
+}
+
 #[derive(Debug, Clone, Copy, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Point<T> {
     pub x: T,